@@ -1,42 +1,34 @@
-use wasm_bindgen::prelude::*;
+use ultra_crustaceous::{ultra_game, Color, Game, Input, PaletteBuffer, ScreenBuffer};
 
-const SCREEN_WIDTH: usize = 320;
-const SCREEN_HEIGHT: usize = 240;
 const TILE_SIZE: usize = 10;
-const OUTPUT_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
-const PALETTE_COLORS: usize = 32;
-const PALETTE_BUFFER_SIZE: usize = PALETTE_COLORS * 2;
 
-static mut OUTPUT_BUFFER: [u8; OUTPUT_BUFFER_SIZE] = [0; OUTPUT_BUFFER_SIZE];
-static mut PALETTE_BUFFER: [u8; PALETTE_BUFFER_SIZE] = [0; PALETTE_BUFFER_SIZE];
-
-#[wasm_bindgen]
-pub fn get_screen_buffer_pointer() -> *const u8 {
-    unsafe { OUTPUT_BUFFER.as_ptr() }
-}
-
-#[wasm_bindgen]
-pub fn get_palette_buffer_pointer() -> *const u8 {
-    unsafe { PALETTE_BUFFER.as_ptr() }
+#[derive(Default)]
+struct Checker {
+    screen: ScreenBuffer,
+    palette: PaletteBuffer,
 }
 
-#[wasm_bindgen]
-pub fn update(_p1: u8, _p2: u8) {
-    for y in 0..SCREEN_HEIGHT {
-        for x in 0..SCREEN_WIDTH {
-            let is_dark_square = (y / TILE_SIZE) % 2 != (x / TILE_SIZE) % 2;
-            let color = if is_dark_square { 0 } else { 1 };
-            let pixel_index = y * SCREEN_WIDTH + x;
-            unsafe {
-                OUTPUT_BUFFER[pixel_index] = color;
+impl Game for Checker {
+    fn update(&mut self, _p1: Input, _p2: Input) {
+        for y in 0..ScreenBuffer::HEIGHT {
+            for x in 0..ScreenBuffer::WIDTH {
+                let is_dark_square = (y / TILE_SIZE) % 2 != (x / TILE_SIZE) % 2;
+                let color = if is_dark_square { 0 } else { 1 };
+                self.screen.set_pixel(x, y, color);
             }
         }
+
+        self.palette[0] = Color::from_rgb4(0x0, 0x0, 0x6); // color 0: blue
+        self.palette[1] = Color::from_rgb4(0x6, 0x0, 0x0); // color 1: red
     }
 
-    unsafe {
-        PALETTE_BUFFER[0] = 0b00000000; // color 0: red
-        PALETTE_BUFFER[1] = 0b00000110; // color 0: green, blue
-        PALETTE_BUFFER[2] = 0b00000110; // color 1: red
-        PALETTE_BUFFER[3] = 0b00000000; // color 1: green, blue
+    fn screen(&self) -> &ScreenBuffer {
+        &self.screen
+    }
+
+    fn palette(&self) -> &PaletteBuffer {
+        &self.palette
     }
 }
+
+ultra_game!(Checker);
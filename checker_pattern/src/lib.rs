@@ -1,27 +1,26 @@
-use wasm_bindgen::prelude::*;
+use ultra_crustaceous::{ultra_game, Game, Input, ScreenBuffer};
 
-const SCREEN_WIDTH: usize = 320;
-const SCREEN_HEIGHT: usize = 240;
 const TILE_SIZE: usize = 10;
-const OUTPUT_BUFFER_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
 
-static mut OUTPUT_BUFFER: [u8; OUTPUT_BUFFER_SIZE] = [0; OUTPUT_BUFFER_SIZE];
-
-#[wasm_bindgen]
-pub fn get_screen_buffer_pointer() -> *const u8 {
-    unsafe { OUTPUT_BUFFER.as_ptr() }
+#[derive(Default)]
+struct Checker {
+    screen: ScreenBuffer,
 }
 
-#[wasm_bindgen]
-pub fn update(_p1: u8, _p2: u8) {
-    for y in 0..SCREEN_HEIGHT {
-        for x in 0..SCREEN_WIDTH {
-            let is_dark_square = (y / TILE_SIZE) % 2 != (x / TILE_SIZE) % 2;
-            let color = if is_dark_square { 0 } else { 1 };
-            let pixel_index = y * SCREEN_WIDTH + x;
-            unsafe {
-                OUTPUT_BUFFER[pixel_index] = color;
+impl Game for Checker {
+    fn update(&mut self, _p1: Input, _p2: Input) {
+        for y in 0..ScreenBuffer::HEIGHT {
+            for x in 0..ScreenBuffer::WIDTH {
+                let is_dark_square = (y / TILE_SIZE) % 2 != (x / TILE_SIZE) % 2;
+                let color = if is_dark_square { 0 } else { 1 };
+                self.screen.set_pixel(x, y, color);
             }
         }
     }
+
+    fn screen(&self) -> &ScreenBuffer {
+        &self.screen
+    }
 }
+
+ultra_game!(Checker);
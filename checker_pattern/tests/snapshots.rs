@@ -0,0 +1,6 @@
+use ultra_snapshot::RomTest;
+
+#[test]
+fn checker() {
+    RomTest::built().assert_frame(0, "tests/snapshots/checker.png");
+}
@@ -1,4 +1,6 @@
-// #![no_std]
+#![no_std]
+
+use core::ops::Range;
 
 // todo: could perhaps relax trait bound on Copy
 /// Implement this trait enable drawing methods on your buffer
@@ -20,15 +22,26 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
 
     fn height(&self) -> i32;
 
+    /// The pixels drawing is limited to, all of the buffer unless overridden
+    ///
+    /// Every drawing method clips to this, so pixels outside of it are never
+    /// set, and it has to be within the buffer. [`Clipped`] overrides it with
+    /// a stack of clip rects.
+    fn clip_rect(&self) -> Rect {
+        Rect::new((0, 0), (self.width(), self.height()))
+    }
+
     // From here on down are default-implementations, can be overridden, if you
     // want to optimize in some way for instance.
 
     // todo: could perhaps move these to extension traits instead?
 
+    /// Sets every pixel within the clip rect
     fn clear(&mut self, color: TColor) {
-        for y in 0..self.height() {
+        let clip = self.clip_rect();
+        for y in clip.y_range() {
             // inner loop on x for efficient memory access
-            for x in 0..self.width() {
+            for x in clip.x_range() {
                 self.set_pixel((x, y), color);
             }
         }
@@ -36,27 +49,28 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
 
     fn draw_rect<TPos: Into<(i32, i32)>>(&mut self, pos: TPos, size: TPos, color: TColor) {
         // limit to screen
-        let (x, y) = pos.into();
-        let (width, height) = size.into();
-        let x_max = (x + width).min(self.width());
-        let y_max = (y + height).min(self.height());
+        let rect = Rect::new(pos, size).intersect(self.clip_rect());
 
-        for y in y..y_max {
+        for y in rect.y_range() {
             // inner loop on x for efficient memory access
-            for x in x..x_max {
+            for x in rect.x_range() {
                 self.set_pixel((x, y), color);
             }
         }
     }
 
+    /// Limits x to the clip rect, its max edge included
     #[inline]
     fn clamp_x(&self, x: i32) -> i32 {
-        x.clamp(0, self.width())
+        let clip = self.clip_rect();
+        x.clamp(clip.min.0, clip.max.0)
     }
 
+    /// Limits y to the clip rect, its max edge included
     #[inline]
     fn clamp_y(&self, y: i32) -> i32 {
-        y.clamp(0, self.height())
+        let clip = self.clip_rect();
+        y.clamp(clip.min.1, clip.max.1)
     }
 
     // maybe use f32 for radius?
@@ -66,16 +80,17 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
         center_y -= 0.5;
 
         // limit to screen
-        let y_max = self.clamp_y((center_y + radius).ceil() as i32);
+        let y_max = self.clamp_y(ceil(center_y + radius));
         let y_min = self.clamp_y((center_y - radius) as i32);
 
-        let x_max = self.clamp_x((center_x + radius).ceil() as i32);
-        let x_min = self.clamp_y((center_x - radius) as i32);
+        let x_max = self.clamp_x(ceil(center_x + radius));
+        let x_min = self.clamp_x((center_x - radius) as i32);
 
         let r_squared = radius * radius;
 
-        for y in y_min..=y_max {
-            let j_squared = (y as f32 - center_y).powi(2);
+        for y in y_min..y_max {
+            let j = y as f32 - center_y;
+            let j_squared = j * j;
 
             // inner loop on x for efficient memory access
             for x in x_min..x_max {
@@ -89,6 +104,247 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
         }
     }
 
+    /// Draws `length` pixels to the right of `pos`, the pixel at `pos`
+    /// included
+    fn draw_hline<TPos: Into<(i32, i32)>>(&mut self, pos: TPos, length: i32, color: TColor) {
+        let (x, y) = pos.into();
+        if !self.clip_rect().y_range().contains(&y) {
+            return;
+        }
+        let x_max = self.clamp_x(x.saturating_add(length));
+        for x in self.clamp_x(x)..x_max {
+            self.set_pixel((x, y), color);
+        }
+    }
+
+    /// Draws `length` pixels upwards from `pos`, the pixel at `pos` included
+    fn draw_vline<TPos: Into<(i32, i32)>>(&mut self, pos: TPos, length: i32, color: TColor) {
+        let (x, y) = pos.into();
+        if !self.clip_rect().x_range().contains(&x) {
+            return;
+        }
+        let y_max = self.clamp_y(y.saturating_add(length));
+        for y in self.clamp_y(y)..y_max {
+            self.set_pixel((x, y), color);
+        }
+    }
+
+    /// Draws a line between two pixels, both of them included
+    ///
+    /// The line takes one pixel per step along its longer axis, like
+    /// Bresenham's algorithm. It's the same pixels whichever end it's drawn
+    /// from.
+    fn draw_line<TPos: Into<(i32, i32)>>(&mut self, from: TPos, to: TPos, color: TColor) {
+        let (x0, y0) = from.into();
+        let (x1, y1) = to.into();
+
+        // clamp both ends first, so the length can't overflow
+        if y0 == y1 {
+            let start = self.clamp_x(x0.min(x1));
+            let end = self.clamp_x(x0.max(x1).saturating_add(1));
+            self.draw_hline((start, y0), end - start, color);
+            return;
+        }
+        if x0 == x1 {
+            let start = self.clamp_y(y0.min(y1));
+            let end = self.clamp_y(y0.max(y1).saturating_add(1));
+            self.draw_vline((x0, start), end - start, color);
+            return;
+        }
+
+        let line = Line::new((x0, y0), (x1, y1));
+        for (x, y) in line.clipped(self.clip_rect()) {
+            self.set_pixel((x, y), color);
+        }
+    }
+
+    /// Draws lines between consecutive points
+    fn draw_polyline<TPos: Into<(i32, i32)>>(
+        &mut self,
+        points: impl IntoIterator<Item = TPos>,
+        color: TColor,
+    ) {
+        let mut points = points.into_iter().map(Into::into);
+        let Some(mut from) = points.next() else {
+            return;
+        };
+        // a single point is still drawn
+        self.draw_line(from, from, color);
+        for to in points {
+            self.draw_line(from, to, color);
+            from = to;
+        }
+    }
+
+    /// Draws a line `width` pixels wide, centered on the line `draw_line`
+    /// draws
+    ///
+    /// The width is measured across the line's longer axis, so diagonal
+    /// lines come out a bit thinner.
+    fn draw_thick_line<TPos: Into<(i32, i32)>>(
+        &mut self,
+        from: TPos,
+        to: TPos,
+        width: i32,
+        color: TColor,
+    ) {
+        if width <= 0 {
+            return;
+        }
+        let line = Line::new(from.into(), to.into());
+        // one span across the longer axis per pixel of the line, with the
+        // extra pixel of even widths below or left of it
+        let offset = -width / 2;
+        for (x, y) in line.clipped_with_margin(self.clip_rect(), width) {
+            if line.is_steep() {
+                self.draw_hline((x.saturating_add(offset), y), width, color);
+            } else {
+                self.draw_vline((x, y.saturating_add(offset)), width, color);
+            }
+        }
+    }
+
+    /// Fills the pixels whose centers are inside a triangle
+    ///
+    /// The corners are on the corners of pixels, so triangles sharing an edge
+    /// neither overlap nor leave a gap between them.
+    fn fill_triangle<TPos: Into<(i32, i32)>>(&mut self, a: TPos, b: TPos, c: TPos, color: TColor) {
+        self.fill_polygon(&[a.into(), b.into(), c.into()], FillRule::NonZero, color);
+    }
+
+    /// Draws the edges of a triangle, with `draw_line`
+    fn draw_triangle<TPos: Into<(i32, i32)>>(&mut self, a: TPos, b: TPos, c: TPos, color: TColor) {
+        let (a, b, c) = (a.into(), b.into(), c.into());
+        self.draw_polyline([a, b, c, a], color);
+    }
+
+    /// Fills the pixels whose centers are inside a polygon, which may be
+    /// concave or cross itself, `rule` decides what's inside then
+    ///
+    /// Like `fill_triangle`, the points are on the corners of pixels, so a
+    /// rectangle fills the same pixels as `draw_rect`. Every row is drawn as
+    /// spans with `draw_hline`, finding each crossing by going through all the
+    /// edges, so it's meant for shapes with few points.
+    fn fill_polygon<TPos: Into<(i32, i32)> + Copy>(
+        &mut self,
+        points: &[TPos],
+        rule: FillRule,
+        color: TColor,
+    ) {
+        let Some(first) = points.first() else {
+            return;
+        };
+        let edges = || {
+            let ends = points.iter().skip(1).chain([first]);
+            points
+                .iter()
+                .zip(ends)
+                .map(|(from, to)| ((*from).into(), (*to).into()))
+        };
+        let (y_min, y_max) = points
+            .iter()
+            .fold((i32::MAX, i32::MIN), |(min, max), point| {
+                let (_, y) = (*point).into();
+                (min.min(y), max.max(y))
+            });
+
+        for y in self.clamp_y(y_min)..self.clamp_y(y_max) {
+            // crossings are visited left to right, the ones at the same x
+            // together
+            let mut x = i64::MIN;
+            let mut winding = 0;
+            let mut crossings = 0;
+            let mut span_start = None;
+            loop {
+                let mut next: Option<(i64, i32, u32)> = None;
+                for (from, to) in edges() {
+                    let Some((crossing, direction)) = crossing(from, to, y) else {
+                        continue;
+                    };
+                    next = match next {
+                        _ if crossing <= x => next,
+                        Some((next_x, ..)) if next_x < crossing => next,
+                        Some((next_x, winding, count)) if next_x == crossing => {
+                            Some((next_x, winding + direction, count + 1))
+                        }
+                        _ => Some((crossing, direction, 1)),
+                    };
+                }
+                let Some((next_x, direction, count)) = next else {
+                    break;
+                };
+                x = next_x;
+                winding += direction;
+                crossings += count;
+
+                match (span_start, rule.is_inside(winding, crossings)) {
+                    (None, true) => span_start = Some(x),
+                    (Some(start), false) => {
+                        let clip = self.clip_rect();
+                        let (min, max) = (clip.min.0 as i64, clip.max.0 as i64);
+                        let (start, end) = (start.clamp(min, max), x.clamp(min, max));
+                        self.draw_hline((start as i32, y), (end - start) as i32, color);
+                        span_start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Draws the edges of a polygon, with `draw_line`
+    ///
+    /// Lines include both of their ends, so the outline of a shape reaches a
+    /// pixel further right and up than `fill_polygon` does.
+    fn draw_polygon<TPos: Into<(i32, i32)> + Copy>(&mut self, points: &[TPos], color: TColor) {
+        self.draw_polyline(
+            points
+                .iter()
+                .chain(points.first())
+                .map(|point| (*point).into()),
+            color,
+        );
+    }
+
+    /// Draws a sprite with its min corner at `pos`, transformed the way
+    /// `options` says
+    ///
+    /// Rotated a quarter turn either way, the sprite is `height` pixels wide
+    /// and `width` high.
+    fn blit<TPos: Into<(i32, i32)>>(
+        &mut self,
+        sprite: &Sprite<TColor>,
+        pos: TPos,
+        options: &Blit<TColor>,
+    ) where
+        TColor: PartialEq,
+    {
+        let (x0, y0) = pos.into();
+        let size = match options.rotation {
+            Rotation::None | Rotation::Half => (sprite.width, sprite.height),
+            Rotation::Quarter | Rotation::ThreeQuarters => (sprite.height, sprite.width),
+        };
+        let rect = Rect::new((x0, y0), size).intersect(self.clip_rect());
+
+        for y in rect.y_range() {
+            // inner loop on x for efficient memory access
+            for x in rect.x_range() {
+                // within the sprite, so it fits in an i32 again
+                let offset = ((x as i64 - x0 as i64) as i32, (y as i64 - y0 as i64) as i32);
+                let color = sprite.get(options.source(sprite, offset));
+                if options.transparent == Some(color) {
+                    continue;
+                }
+                let color = options
+                    .remap
+                    .iter()
+                    .find(|(from, _)| *from == color)
+                    .map_or(color, |(_, to)| *to);
+                self.set_pixel((x, y), color);
+            }
+        }
+    }
+
     fn draw_rect_with<TPos: Into<(i32, i32)>, TArg: From<(i32, i32)>>(
         &mut self,
         pos: TPos,
@@ -96,14 +352,11 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
         color_fn: fn(TArg) -> TColor,
     ) {
         // limit to screen
-        let (x, y) = pos.into();
-        let (width, height) = size.into();
-        let x_max = (x + width).min(self.width());
-        let y_max = (y + height).min(self.height());
+        let rect = Rect::new(pos, size).intersect(self.clip_rect());
 
-        for y in y..y_max {
+        for y in rect.y_range() {
             // inner loop on x for efficient memory access
-            for x in x..x_max {
+            for x in rect.x_range() {
                 let pos = (x, y);
                 self.set_pixel(pos, color_fn(pos.into()));
             }
@@ -117,14 +370,11 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
         mutator: fn(TArg, TColor) -> TColor,
     ) {
         // limit to screen
-        let (x, y) = pos.into();
-        let (width, height) = size.into();
-        let x_max = (x + width).min(self.width());
-        let y_max = (y + height).min(self.height());
+        let rect = Rect::new(pos, size).intersect(self.clip_rect());
 
-        for y in y..y_max {
+        for y in rect.y_range() {
             // inner loop on x for efficient memory access
-            for x in x..x_max {
+            for x in rect.x_range() {
                 let pos = (x, y);
                 // perf: slightly inefficient (extra copies and writes)
                 let current_color = self.get_pixel(pos);
@@ -134,10 +384,937 @@ pub trait PixelBuffer<TColor: Clone + Copy> {
     }
 }
 
+/// A rectangle of pixels, its min corner included and its max corner not
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl Rect {
+    /// `size` pixels from `pos`, empty if the size is negative
+    pub fn new<TPos: Into<(i32, i32)>>(pos: TPos, size: TPos) -> Self {
+        let (x, y) = pos.into();
+        let (width, height) = size.into();
+        Self::from_corners((x, y), (x.saturating_add(width), y.saturating_add(height)))
+    }
+
+    /// From `min` up to `max`, which isn't included
+    pub fn from_corners<TPos: Into<(i32, i32)>>(min: TPos, max: TPos) -> Self {
+        let (min, max) = (min.into(), max.into());
+        // so empty rects still have ranges that can be clamped to
+        Self {
+            min,
+            max: (max.0.max(min.0), max.1.max(min.1)),
+        }
+    }
+
+    pub fn min(&self) -> (i32, i32) {
+        self.min
+    }
+
+    pub fn max(&self) -> (i32, i32) {
+        self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.0 == self.max.0 || self.min.1 == self.max.1
+    }
+
+    pub fn contains<TPos: Into<(i32, i32)>>(&self, pos: TPos) -> bool {
+        let (x, y) = pos.into();
+        self.x_range().contains(&x) && self.y_range().contains(&y)
+    }
+
+    /// The pixels in both rects
+    pub fn intersect(self, other: Rect) -> Rect {
+        Self::from_corners(
+            (self.min.0.max(other.min.0), self.min.1.max(other.min.1)),
+            (self.max.0.min(other.max.0), self.max.1.min(other.max.1)),
+        )
+    }
+
+    fn x_range(&self) -> Range<i32> {
+        self.min.0..self.max.0
+    }
+
+    fn y_range(&self) -> Range<i32> {
+        self.min.1..self.max.1
+    }
+}
+
+/// A buffer with a stack of up to `DEPTH` clip rects, drawing is limited to
+/// the one on top
+///
+/// ```ignore
+/// let mut clipped = Clipped::<_>::new(&mut screen);
+/// clipped.push_clip(Rect::new((10, 10), (100, 50)));
+/// // only the part within the rect is drawn
+/// clipped.draw_circle((10.0, 10.0), 20.0, color);
+/// clipped.pop_clip();
+/// ```
+pub struct Clipped<'a, TBuffer, const DEPTH: usize = 8> {
+    buffer: &'a mut TBuffer,
+    clips: [Rect; DEPTH],
+    len: usize,
+}
+
+impl<'a, TBuffer, const DEPTH: usize> Clipped<'a, TBuffer, DEPTH> {
+    /// Without any clip rects, so all of the buffer can be drawn on
+    pub fn new(buffer: &'a mut TBuffer) -> Self {
+        Self {
+            buffer,
+            clips: [Rect::from_corners((0, 0), (0, 0)); DEPTH],
+            len: 0,
+        }
+    }
+
+    /// Limits drawing to the part of `clip` within the current clip rect
+    ///
+    /// Panics if there already are `DEPTH` clip rects.
+    pub fn push_clip(&mut self, clip: Rect) {
+        assert!(self.len < DEPTH, "more than {DEPTH} clip rects");
+        self.clips[self.len] = match self.len {
+            0 => clip,
+            len => clip.intersect(self.clips[len - 1]),
+        };
+        self.len += 1;
+    }
+
+    /// Goes back to the clip rect before the last `push_clip`, returning the
+    /// one removed
+    pub fn pop_clip(&mut self) -> Option<Rect> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.clips[self.len])
+    }
+}
+
+impl<TColor: Clone + Copy, TBuffer: PixelBuffer<TColor>, const DEPTH: usize> PixelBuffer<TColor>
+    for Clipped<'_, TBuffer, DEPTH>
+{
+    /// Ignored outside the clip rect
+    fn set_pixel<TPos: Into<(i32, i32)>>(&mut self, pos: TPos, color: TColor) {
+        let pos = pos.into();
+        if self.clip_rect().contains(pos) {
+            self.buffer.set_pixel(pos, color);
+        }
+    }
+
+    fn get_pixel<TPos: Into<(i32, i32)>>(&self, pos: TPos) -> TColor {
+        self.buffer.get_pixel(pos)
+    }
+
+    fn width(&self) -> i32 {
+        self.buffer.width()
+    }
+
+    fn height(&self) -> i32 {
+        self.buffer.height()
+    }
+
+    fn clip_rect(&self) -> Rect {
+        let clip = self.buffer.clip_rect();
+        match self.len {
+            0 => clip,
+            len => self.clips[len - 1].intersect(clip),
+        }
+    }
+}
+
+/// Pixel art for [`PixelBuffer::blit`], in the buffer's own pixel format, so
+/// palette indices for a buffer of them
+///
+/// The pixels are in rows, like buffers, so on a screen with y up the first
+/// row is the bottom one.
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite<'a, TColor> {
+    width: i32,
+    height: i32,
+    pixels: &'a [TColor],
+}
+
+impl<'a, TColor: Copy> Sprite<'a, TColor> {
+    /// Panics unless there are `width * height` pixels
+    pub const fn new(width: i32, height: i32, pixels: &'a [TColor]) -> Self {
+        assert!(
+            width >= 0 && height >= 0 && pixels.len() == width as usize * height as usize,
+            "sprite pixels don't match its size"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// The pixel at `(x, y)`, which has to be within the sprite
+    pub fn get(&self, (x, y): (i32, i32)) -> TColor {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+}
+
+/// How [`PixelBuffer::blit`] draws a sprite, `Blit::default()` draws it as it
+/// is
+///
+/// The sprite is flipped before it's rotated.
+#[derive(Clone, Copy, Debug)]
+pub struct Blit<'a, TColor> {
+    /// Pixels of this color aren't drawn, checked before `remap`
+    pub transparent: Option<TColor>,
+    /// Mirrors the sprite left to right
+    pub flip_x: bool,
+    /// Mirrors the sprite bottom to top
+    pub flip_y: bool,
+    pub rotation: Rotation,
+    /// Pixels of the first color of a pair are drawn in the second, to reuse
+    /// a sprite in other colors
+    pub remap: &'a [(TColor, TColor)],
+}
+
+impl<TColor> Default for Blit<'_, TColor> {
+    fn default() -> Self {
+        Self {
+            transparent: None,
+            flip_x: false,
+            flip_y: false,
+            rotation: Rotation::None,
+            remap: &[],
+        }
+    }
+}
+
+impl<TColor: Copy> Blit<'_, TColor> {
+    /// The pixel of the sprite drawn `offset` from the min corner
+    fn source(&self, sprite: &Sprite<TColor>, (x, y): (i32, i32)) -> (i32, i32) {
+        let (width, height) = (sprite.width, sprite.height);
+        // undo the rotation, then the flips
+        let (x, y) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Quarter => (y, height - 1 - x),
+            Rotation::Half => (width - 1 - x, height - 1 - y),
+            Rotation::ThreeQuarters => (width - 1 - y, x),
+        };
+        (
+            if self.flip_x { width - 1 - x } else { x },
+            if self.flip_y { height - 1 - y } else { y },
+        )
+    }
+}
+
+/// Quarter turns counterclockwise, on a buffer with y up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+/// What `fill_polygon` counts as inside, which only matters where a polygon
+/// crosses or overlaps itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FillRule {
+    /// Inside when a ray from it crosses an odd number of edges, so where a
+    /// polygon overlaps itself is left empty
+    EvenOdd,
+    /// Inside when the edges wind around it, so overlaps are filled, unless
+    /// they go around in opposite directions
+    NonZero,
+}
+
+impl FillRule {
+    fn is_inside(self, winding: i32, crossings: u32) -> bool {
+        match self {
+            FillRule::EvenOdd => crossings % 2 == 1,
+            FillRule::NonZero => winding != 0,
+        }
+    }
+}
+
+/// Where an edge crosses the middle of row `y`, as the first pixel with its
+/// center right of it, and whether the edge goes up or down
+fn crossing((x0, y0): (i32, i32), (x1, y1): (i32, i32), y: i32) -> Option<(i64, i32)> {
+    let ((x0, y0), (x1, y1), direction) = if y0 < y1 {
+        ((x0, y0), (x1, y1), 1)
+    } else {
+        ((x1, y1), (x0, y0), -1)
+    };
+    // half-open, so horizontal edges are skipped and a vertex isn't counted
+    // twice
+    if y < y0 || y >= y1 {
+        return None;
+    }
+
+    // twice the x of the crossing is `numerator / height`
+    let height = y1 as i128 - y0 as i128;
+    let numerator =
+        2 * x0 as i128 * height + (2 * y as i128 + 1 - 2 * y0 as i128) * (x1 as i128 - x0 as i128);
+    // the first x with `2 * x + 1 >= numerator / height`
+    let x = (numerator - height + 2 * height - 1).div_euclid(2 * height);
+    Some((x as i64, direction))
+}
+
+/// The pixels of a line, one per step along its longer axis
+#[derive(Clone, Copy)]
+struct Line {
+    from: (i64, i64),
+    delta: (i64, i64),
+    steps: i64,
+}
+
+impl Line {
+    fn new((x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> Self {
+        let delta = (x1 as i64 - x0 as i64, y1 as i64 - y0 as i64);
+        Self {
+            from: (x0 as i64, y0 as i64),
+            delta,
+            steps: delta.0.abs().max(delta.1.abs()),
+        }
+    }
+
+    /// Whether the longer axis is y
+    fn is_steep(&self) -> bool {
+        self.delta.1.abs() > self.delta.0.abs()
+    }
+
+    /// The pixel at a step, with the shorter axis rounded to the nearest pixel
+    fn at(&self, step: i64) -> (i64, i64) {
+        if self.steps == 0 {
+            return self.from;
+        }
+        // rounds halves up, in both directions, which keeps lines symmetric
+        let offset = |delta: i64| {
+            let scaled = 2 * step as i128 * delta as i128 + self.steps as i128;
+            scaled.div_euclid(2 * self.steps as i128) as i64
+        };
+        (
+            self.from.0 + offset(self.delta.0),
+            self.from.1 + offset(self.delta.1),
+        )
+    }
+
+    fn clipped(self, clip: Rect) -> impl Iterator<Item = (i32, i32)> {
+        self.clipped_with_margin(clip, 1)
+    }
+
+    /// The pixels within `margin - 1` pixels of `clip`, only stepping through
+    /// the part of the line that can be in it
+    fn clipped_with_margin(self, clip: Rect, margin: i32) -> impl Iterator<Item = (i32, i32)> {
+        let margin = margin as i64 - 1;
+        let (x_range, y_range) = (
+            (clip.min.0 as i64 - margin, clip.max.0 as i64 + margin),
+            (clip.min.1 as i64 - margin, clip.max.1 as i64 + margin),
+        );
+
+        // the longer axis moves by one every step, so the visible steps are
+        // the ones where it's in range
+        let (start, direction, (min, max)) = if self.is_steep() {
+            (self.from.1, self.delta.1.signum(), y_range)
+        } else {
+            (self.from.0, self.delta.0.signum(), x_range)
+        };
+        let (first, last) = match direction {
+            1 => (min - start, max - 1 - start),
+            -1 => (start - (max - 1), start - min),
+            _ => (0, 0),
+        };
+
+        (first.max(0)..=last.min(self.steps))
+            .map(move |step| self.at(step))
+            .filter(move |(x, y)| {
+                (x_range.0..x_range.1).contains(x) && (y_range.0..y_range.1).contains(y)
+            })
+            .map(|(x, y)| (x as i32, y as i32))
+    }
+}
+
+/// `f32::ceil` is in `std`, not `core`
+fn ceil(x: f32) -> i32 {
+    let truncated = x as i32;
+    if (truncated as f32) < x {
+        truncated.saturating_add(1)
+    } else {
+        truncated
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    extern crate std;
+    use std::{string::String, vec, vec::Vec};
+
+    /// Panics when drawn outside of, or outside `clip`, so tests catch missing
+    /// clipping
+    struct TestBuffer {
+        width: i32,
+        height: i32,
+        pixels: Vec<u8>,
+        clip: Option<Rect>,
+    }
+
+    impl TestBuffer {
+        fn new(width: i32, height: i32) -> Self {
+            Self {
+                width,
+                height,
+                pixels: vec![0; (width * height) as usize],
+                clip: None,
+            }
+        }
+
+        /// The pixels as `#` and `.`, top row first
+        fn rows(&self) -> Vec<String> {
+            (0..self.height)
+                .rev()
+                .map(|y| {
+                    (0..self.width)
+                        .map(|x| match self.get_pixel((x, y)) {
+                            0 => '.',
+                            _ => '#',
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    impl PixelBuffer<u8> for TestBuffer {
+        fn set_pixel<T: Into<(i32, i32)>>(&mut self, pos: T, color: u8) {
+            let (x, y) = pos.into();
+            assert!(
+                (0..self.width).contains(&x) && (0..self.height).contains(&y),
+                "drew outside the buffer at {x}, {y}"
+            );
+            assert!(
+                self.clip_rect().contains((x, y)),
+                "drew outside the clip rect at {x}, {y}"
+            );
+            self.pixels[(y * self.width + x) as usize] = color;
+        }
+
+        fn get_pixel<T: Into<(i32, i32)>>(&self, pos: T) -> u8 {
+            let (x, y) = pos.into();
+            self.pixels[(y * self.width + x) as usize]
+        }
+
+        fn width(&self) -> i32 {
+            self.width
+        }
+
+        fn height(&self) -> i32 {
+            self.height
+        }
+
+        fn clip_rect(&self) -> Rect {
+            let buffer = Rect::new((0, 0), (self.width, self.height));
+            self.clip.map_or(buffer, |clip| clip.intersect(buffer))
+        }
+    }
+
+    /// Xorshift, random enough for shapes
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn range(&mut self, range: Range<i32>) -> i32 {
+            let len = (range.end as i64 - range.start as i64) as u64;
+            (range.start as i64 + (self.next() % len) as i64) as i32
+        }
+
+        /// Mostly around a buffer `size` pixels wide, sometimes far away
+        fn coord(&mut self, size: i32) -> i32 {
+            match self.next() % 16 {
+                0 => i32::MIN,
+                1 => i32::MAX,
+                2 => self.range(-100_000..100_000),
+                _ => self.range(-size..size * 2),
+            }
+        }
+
+        fn pos(&mut self, width: i32, height: i32) -> (i32, i32) {
+            (self.coord(width), self.coord(height))
+        }
+    }
+
+    #[test]
+    fn rects() {
+        let mut buffer = TestBuffer::new(4, 3);
+        buffer.draw_rect((-2, -1), (4, 3), 1);
+        buffer.draw_rect((3, 2), (5, 5), 1);
+        // negative sizes are empty
+        buffer.draw_rect((3, 1), (-2, -1), 1);
+        assert_eq!(buffer.rows(), ["...#", "##..", "##.."]);
+
+        buffer.draw_rect_with((-1, 1), (3, 9), |(x, y): (i32, i32)| (x + y) as u8);
+        buffer.modify_rect_with((2, -5), (9, 6), |_: (i32, i32), color| color + 1);
+        assert_eq!(buffer.rows(), ["##.#", "##..", "####"]);
+        assert_eq!(buffer.get_pixel((1, 2)), 3);
+    }
+
+    #[test]
+    fn circles() {
+        let mut buffer = TestBuffer::new(6, 6);
+        buffer.draw_circle((3.0, 3.0), 2.0, 1);
+        assert_eq!(
+            buffer.rows(),
+            ["......", "..##..", ".####.", ".####.", "..##..", "......"]
+        );
+
+        // right at the edges, the right and top ones used to be drawn past
+        let mut buffer = TestBuffer::new(6, 6);
+        buffer.draw_circle((6.0, 6.0), 2.0, 1);
+        buffer.draw_circle((0.0, 0.0), 2.0, 1);
+        buffer.draw_circle((6.0, 0.0), 1e9, 1);
+        assert_eq!(buffer.rows(), ["######"; 6]);
+    }
+
+    #[test]
+    fn clip_stack() {
+        let mut buffer = TestBuffer::new(6, 4);
+        let mut clipped = Clipped::<_, 2>::new(&mut buffer);
+        assert_eq!(clipped.clip_rect(), Rect::new((0, 0), (6, 4)));
+
+        clipped.push_clip(Rect::new((1, -5), (4, 7)));
+        clipped.push_clip(Rect::new((3, 1), (9, 9)));
+        assert_eq!(clipped.clip_rect(), Rect::from_corners((3, 1), (5, 2)));
+        clipped.clear(1);
+
+        assert_eq!(clipped.pop_clip(), Some(Rect::from_corners((3, 1), (5, 2))));
+        clipped.draw_line((0, 0), (5, 0), 2);
+        // set_pixel is clipped too
+        clipped.set_pixel((0, 3), 2);
+        assert_eq!(clipped.pop_clip(), Some(Rect::new((1, -5), (4, 7))));
+        assert_eq!(clipped.pop_clip(), None);
+        clipped.set_pixel((5, 3), 2);
+
+        assert_eq!(buffer.rows(), [".....#", "......", "...##.", ".####."]);
+    }
+
+    #[test]
+    #[should_panic(expected = "more than 1 clip rects")]
+    fn clip_stack_overflow() {
+        let mut buffer = TestBuffer::new(1, 1);
+        let mut clipped = Clipped::<_, 1>::new(&mut buffer);
+        clipped.push_clip(Rect::new((0, 0), (1, 1)));
+        clipped.push_clip(Rect::new((0, 0), (1, 1)));
+    }
+
+    /// Random shapes, some of them huge, in buffers with random clip rects
+    /// never draw outside of the clip rect, which `TestBuffer` checks
+    #[test]
+    fn nothing_drawn_outside() {
+        let mut rng = Rng(0x5eed_1234_abcd_ef01);
+        for _ in 0..500 {
+            let (width, height) = (rng.range(1..20), rng.range(1..15));
+            let mut buffer = TestBuffer::new(width, height);
+            if rng.range(0..4) > 0 {
+                let (pos, size) = (
+                    (rng.range(-5..width + 5), rng.range(-5..height + 5)),
+                    (rng.range(-2..width), rng.range(-2..height)),
+                );
+                buffer.clip = Some(Rect::new(pos, size));
+            }
+
+            let mut pos = || rng.pos(width, height);
+            let (a, b, c, d) = (pos(), pos(), pos(), pos());
+            buffer.draw_rect(a, b, 1);
+            buffer.draw_rect_with(c, d, |(x, y): (i32, i32)| (x ^ y) as u8);
+            buffer.modify_rect_with(a, c, |_: (i32, i32), color| color ^ 1);
+            buffer.draw_hline(a, b.0, 1);
+            buffer.draw_vline(c, d.1, 1);
+            buffer.draw_line(a, b, 1);
+            buffer.draw_polyline([a, b, c, d], 1);
+            buffer.fill_triangle(a, b, c, 1);
+            buffer.draw_triangle(b, c, d, 1);
+            buffer.fill_polygon(&[a, b, c, d], FillRule::EvenOdd, 1);
+            buffer.fill_polygon(&[d, c, a, b], FillRule::NonZero, 1);
+            buffer.draw_polygon(&[a, c, b, d], 1);
+
+            let width = rng.range(-1..12);
+            buffer.draw_thick_line(a, d, width, 1);
+
+            let center = (
+                rng.coord(buffer.width) as f32 / 3.0,
+                rng.coord(buffer.height) as f32 / 3.0,
+            );
+            let radius = match rng.next() % 8 {
+                0 => 1e12,
+                _ => rng.range(-2..30) as f32 / 2.0,
+            };
+            buffer.draw_circle(center, radius, 1);
+
+            buffer.clear(1);
+            let clip = buffer.clip_rect();
+            for y in 0..buffer.height {
+                for x in 0..buffer.width {
+                    let expected = clip.contains((x, y)) as u8;
+                    assert_eq!(buffer.get_pixel((x, y)), expected, "{x}, {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hline_and_vline() {
+        let mut buffer = TestBuffer::new(5, 4);
+        buffer.draw_hline((1, 3), 3, 1);
+        buffer.draw_vline((0, 0), 2, 1);
+        // clipped
+        buffer.draw_hline((-10, 0), 12, 1);
+        buffer.draw_vline((4, 1), 100, 1);
+        buffer.draw_hline((0, 4), 5, 1);
+        buffer.draw_vline((-1, 0), 5, 1);
+        // nothing
+        buffer.draw_hline((2, 2), -2, 1);
+
+        assert_eq!(buffer.rows(), [".####", "....#", "#...#", "##..."]);
+    }
+
+    #[test]
+    fn lines() {
+        let mut buffer = TestBuffer::new(7, 4);
+        buffer.draw_line((0, 0), (6, 3), 1);
+        // halfway pixels round up, from both ends
+        assert_eq!(buffer.rows(), [".....##", "...##..", ".##....", "#......"]);
+
+        let mut buffer = TestBuffer::new(3, 5);
+        buffer.draw_line((0, 4), (2, 0), 1);
+        assert_eq!(buffer.rows(), ["#..", ".#.", ".#.", "..#", "..#"]);
+
+        let mut buffer = TestBuffer::new(2, 2);
+        buffer.draw_line((1, 1), (1, 1), 1);
+        assert_eq!(buffer.rows(), [".#", ".."]);
+    }
+
+    #[test]
+    fn lines_are_symmetric() {
+        let ends = [(0, 0), (9, 4), (3, 7), (-2, 5), (8, 8), (5, -3)];
+        for from in ends {
+            for to in ends {
+                let mut forwards = TestBuffer::new(8, 8);
+                forwards.draw_line(from, to, 1);
+                let mut backwards = TestBuffer::new(8, 8);
+                backwards.draw_line(to, from, 1);
+                assert_eq!(forwards.rows(), backwards.rows(), "{from:?} to {to:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn clipped_lines() {
+        let mut clipped = TestBuffer::new(4, 4);
+        clipped.draw_line((-4, -2), (8, 4), 1);
+
+        let mut big = TestBuffer::new(16, 12);
+        big.draw_line((0, 0), (12, 6), 1);
+        let inside: Vec<String> = big.rows()[6..10]
+            .iter()
+            .map(|row| row[4..8].into())
+            .collect();
+        assert_eq!(clipped.rows(), inside);
+
+        // only the visible part is stepped through, these would take ages
+        // otherwise
+        let mut buffer = TestBuffer::new(4, 4);
+        buffer.draw_line((i32::MIN, i32::MIN), (i32::MAX, i32::MAX), 1);
+        assert_eq!(buffer.rows(), ["...#", "..#.", ".#..", "#..."]);
+        buffer.draw_line((i32::MAX, 0), (i32::MIN, 1), 1);
+        buffer.draw_thick_line((0, i32::MIN), (3, i32::MAX), 3, 1);
+
+        // straight lines across the whole range still fill their row or column
+        let mut buffer = TestBuffer::new(4, 4);
+        buffer.draw_line((i32::MIN, 1), (i32::MAX, 1), 1);
+        buffer.draw_line((2, i32::MAX), (2, i32::MIN), 1);
+        assert_eq!(buffer.rows(), ["..#.", "..#.", "####", "..#."]);
+    }
+
+    #[test]
+    fn polyline() {
+        let mut buffer = TestBuffer::new(4, 3);
+        buffer.draw_polyline([(0, 0), (3, 0), (3, 2), (0, 2)], 1);
+        assert_eq!(buffer.rows(), ["####", "...#", "####"]);
+
+        let mut buffer = TestBuffer::new(2, 1);
+        buffer.draw_polyline([(1, 0)], 1);
+        buffer.draw_polyline(Vec::<(i32, i32)>::new(), 1);
+        assert_eq!(buffer.rows(), [".#"]);
+    }
+
+    #[test]
+    fn thick_lines() {
+        let mut buffer = TestBuffer::new(6, 5);
+        buffer.draw_thick_line((0, 2), (5, 2), 3, 1);
+        assert_eq!(
+            buffer.rows(),
+            ["......", "######", "######", "######", "......"]
+        );
+
+        // even widths have the extra pixel to the left
+        let mut buffer = TestBuffer::new(5, 3);
+        buffer.draw_thick_line((2, 0), (2, 2), 2, 1);
+        assert_eq!(buffer.rows(), [".##..", ".##..", ".##.."]);
+    }
+
+    #[test]
+    fn triangles() {
+        let mut buffer = TestBuffer::new(6, 4);
+        buffer.fill_triangle((0, 0), (6, 0), (0, 4), 1);
+        assert_eq!(buffer.rows(), ["#.....", "##....", "####..", "#####."]);
+
+        // either winding order
+        let mut reversed = TestBuffer::new(6, 4);
+        reversed.fill_triangle((0, 4), (6, 0), (0, 0), 1);
+        assert_eq!(reversed.rows(), buffer.rows());
+
+        let mut buffer = TestBuffer::new(5, 3);
+        buffer.draw_triangle((0, 0), (4, 0), (2, 2), 1);
+        assert_eq!(buffer.rows(), ["..#..", ".#.#.", "#####"]);
+    }
+
+    #[test]
+    fn triangles_share_edges() {
+        let corners = [(-1, 0), (9, 2), (3, 8), (7, 7)];
+        let mut first = TestBuffer::new(8, 8);
+        first.fill_triangle(corners[0], corners[1], corners[2], 1);
+        let mut second = TestBuffer::new(8, 8);
+        second.fill_triangle(corners[1], corners[3], corners[2], 1);
+
+        let mut both = TestBuffer::new(8, 8);
+        both.fill_polygon(
+            &[corners[0], corners[1], corners[3], corners[2]],
+            FillRule::NonZero,
+            1,
+        );
+
+        for i in 0..both.pixels.len() {
+            assert_eq!(
+                first.pixels[i] + second.pixels[i],
+                both.pixels[i],
+                "pixel {i}"
+            );
+        }
+    }
+
+    #[test]
+    fn polygons() {
+        let mut polygon = TestBuffer::new(6, 5);
+        polygon.fill_polygon(&[(1, 1), (5, 1), (5, 4), (1, 4)], FillRule::EvenOdd, 1);
+        let mut rect = TestBuffer::new(6, 5);
+        rect.draw_rect((1, 1), (4, 3), 1);
+        assert_eq!(polygon.rows(), rect.rows());
+
+        // concave
+        let mut buffer = TestBuffer::new(5, 4);
+        let points = [
+            (0, 0),
+            (5, 0),
+            (5, 4),
+            (3, 4),
+            (3, 2),
+            (2, 2),
+            (2, 4),
+            (0, 4),
+        ];
+        buffer.fill_polygon(&points, FillRule::EvenOdd, 1);
+        assert_eq!(buffer.rows(), ["##.##", "##.##", "#####", "#####"]);
+
+        let mut buffer = TestBuffer::new(6, 5);
+        buffer.draw_polygon(&[(0, 0), (5, 0), (5, 4), (0, 4)], 1);
+        assert_eq!(
+            buffer.rows(),
+            ["######", "#....#", "#....#", "#....#", "######"]
+        );
+
+        // nothing to fill
+        buffer.fill_polygon(&[] as &[(i32, i32)], FillRule::NonZero, 2);
+        buffer.fill_polygon(&[(1, 1), (3, 3), (4, 4)], FillRule::NonZero, 2);
+        buffer.draw_polygon(&[] as &[(i32, i32)], 2);
+        assert!(!buffer.pixels.contains(&2));
+    }
+
+    #[test]
+    fn fill_rules() {
+        // an outer square with an inner one going around the same way,
+        // connected by an edge there and back
+        let points = [
+            (0, 0),
+            (6, 0),
+            (6, 6),
+            (0, 6),
+            (0, 0),
+            (2, 2),
+            (4, 2),
+            (4, 4),
+            (2, 4),
+            (2, 2),
+        ];
+        let mut buffer = TestBuffer::new(6, 6);
+        buffer.fill_polygon(&points, FillRule::EvenOdd, 1);
+        let with_hole = ["######", "######", "##..##", "##..##", "######", "######"];
+        assert_eq!(buffer.rows(), with_hole);
+
+        let mut buffer = TestBuffer::new(6, 6);
+        buffer.fill_polygon(&points, FillRule::NonZero, 1);
+        assert_eq!(buffer.rows(), ["######"; 6]);
+
+        // going around the other way makes a hole with either rule
+        let mut points = points;
+        points[5..].reverse();
+        let mut buffer = TestBuffer::new(6, 6);
+        buffer.fill_polygon(&points, FillRule::NonZero, 1);
+        assert_eq!(buffer.rows(), with_hole);
+
+        // twice around the same square
+        let points = [
+            (0, 0),
+            (2, 0),
+            (2, 2),
+            (0, 2),
+            (0, 0),
+            (2, 0),
+            (2, 2),
+            (0, 2),
+        ];
+        let mut buffer = TestBuffer::new(2, 2);
+        buffer.fill_polygon(&points, FillRule::EvenOdd, 1);
+        assert_eq!(buffer.rows(), ["..", ".."]);
+        buffer.fill_polygon(&points, FillRule::NonZero, 1);
+        assert_eq!(buffer.rows(), ["##", "##"]);
+    }
+
+    #[test]
+    fn clipped_polygons() {
+        let mut buffer = TestBuffer::new(4, 4);
+        buffer.fill_triangle(
+            (i32::MIN, i32::MIN),
+            (i32::MAX, i32::MIN),
+            (i32::MAX, i32::MAX),
+            1,
+        );
+        assert_eq!(buffer.rows(), ["...#", "..##", ".###", "####"]);
+
+        let mut buffer = TestBuffer::new(4, 4);
+        buffer.fill_polygon(
+            &[(-3, -3), (10, -3), (10, 2), (-3, 2)],
+            FillRule::EvenOdd,
+            1,
+        );
+        assert_eq!(buffer.rows(), ["....", "....", "####", "####"]);
+    }
+
+    #[test]
+    fn blit() {
+        // an L, bottom row first, with a transparent pixel and a 2 to remap
+        #[rustfmt::skip]
+        let sprite = Sprite::new(3, 2, &[
+            1, 2, 2,
+            1, 0, 0,
+        ]);
+        let draw = |options: Blit<u8>| {
+            let mut buffer = TestBuffer::new(4, 4);
+            buffer.clear(5);
+            buffer.blit(&sprite, (1, 1), &options);
+            let rows: Vec<String> = (0..4)
+                .rev()
+                .map(|y| {
+                    (0..4)
+                        .map(|x| (b'0' + buffer.get_pixel((x, y))) as char)
+                        .collect()
+                })
+                .collect();
+            rows
+        };
+
+        assert_eq!(draw(Blit::default()), ["5555", "5100", "5122", "5555"]);
+
+        let options = Blit {
+            transparent: Some(0),
+            remap: &[(2, 3), (7, 1)],
+            ..Blit::default()
+        };
+        assert_eq!(draw(options), ["5555", "5155", "5133", "5555"]);
+
+        let flip_x = Blit {
+            flip_x: true,
+            ..Blit::default()
+        };
+        assert_eq!(draw(flip_x), ["5555", "5001", "5221", "5555"]);
+        let flip_y = Blit {
+            flip_y: true,
+            ..Blit::default()
+        };
+        assert_eq!(draw(flip_y), ["5555", "5122", "5100", "5555"]);
+
+        let rotated = |rotation| Blit {
+            rotation,
+            ..Blit::default()
+        };
+        assert_eq!(
+            draw(rotated(Rotation::Quarter)),
+            ["5025", "5025", "5115", "5555"]
+        );
+        assert_eq!(
+            draw(rotated(Rotation::Half)),
+            ["5555", "5221", "5001", "5555"]
+        );
+        assert_eq!(
+            draw(rotated(Rotation::ThreeQuarters)),
+            ["5115", "5205", "5205", "5555"]
+        );
+
+        // flipped first, then rotated
+        let options = Blit {
+            flip_x: true,
+            rotation: Rotation::Quarter,
+            ..Blit::default()
+        };
+        assert_eq!(draw(options), ["5115", "5025", "5025", "5555"]);
+    }
+
+    #[test]
+    fn clipped_blit() {
+        let sprite = Sprite::new(3, 3, &[1; 9]);
+        let mut buffer = TestBuffer::new(4, 4);
+        buffer.blit(&sprite, (-1, 2), &Blit::default());
+        buffer.blit(&sprite, (i32::MAX, i32::MIN), &Blit::default());
+        buffer.blit(&Sprite::new(0, 0, &[]), (1, 1), &Blit::default());
+
+        let mut clipped = Clipped::<_>::new(&mut buffer);
+        clipped.push_clip(Rect::new((3, 0), (1, 1)));
+        clipped.blit(&sprite, (2, -1), &Blit::default());
+        assert_eq!(buffer.rows(), ["##..", "##..", "....", "...#"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "sprite pixels don't match its size")]
+    fn sprite_size() {
+        Sprite::new(2, 2, &[0u8; 3]);
+    }
+
+    #[test]
+    fn ceil_matches_std() {
+        for x in [-2.5, -2.0, -0.5, 0.0, 0.25, 1.0, 1.5, 319.9] {
+            assert_eq!(ceil(x), x.ceil() as i32, "{x}");
+        }
+    }
+
     #[test]
     fn basic_usage() {
         const WIDTH: usize = 320;
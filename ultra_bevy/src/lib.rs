@@ -7,10 +7,16 @@ extern crate wee_alloc;
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 use bevy::prelude::*;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 use send_wrapper::SendWrapper;
 pub use ultra_crustaceous::Color as UltraColor;
 pub use ultra_crustaceous::{self};
-use ultra_crustaceous::{PaletteBuffer, ScreenBuffer};
+use ultra_crustaceous::{
+    AudioBuffer, Input, InputTracker, PaletteBuffer, SaveBuffer, ScreenBuffer, MAX_SAVE_SIZE,
+};
+use ultra_synth::Synth;
+pub use ultra_synth::{self};
 use wasm_bindgen::prelude::*;
 
 // everything has to be static state, but we hide that as best as we can from the user
@@ -19,9 +25,10 @@ static mut BEVY_APP: Option<SendWrapper<App>> = None;
 pub use ultra_bevy_derive::init;
 
 pub mod prelude {
-    pub use crate::{UltraColor, UltraInput, UltraPlugin};
-    pub use rastateur::PixelBuffer;
+    pub use crate::{SaveData, UltraColor, UltraInput, UltraPlugin, UltraRng};
+    pub use rastateur::{Blit, PixelBuffer, Rotation, Sprite};
     pub use ultra_crustaceous::*;
+    pub use ultra_synth::{note, Adsr, Duty, Synth};
 }
 
 #[wasm_bindgen]
@@ -50,14 +57,105 @@ pub fn get_palette_buffer_pointer() -> *const u8 {
         .expect(
             "Couldn't find output buffer resource in bevy app. Did you forget to add UltraPlugin?",
         )
-        .as_ptr() as *const u8
+        .as_bytes()
+        .as_ptr()
+    }
+}
+
+#[wasm_bindgen]
+pub fn get_audio_buffer_pointer() -> *const i16 {
+    unsafe {
+        BEVY_APP
+        .as_ref()
+        .unwrap()
+        .world
+        .get_resource::<AudioBuffer>()
+        .expect(
+            "Couldn't find audio buffer resource in bevy app. Did you forget to add UltraPlugin?",
+        )
+        .as_ptr()
+    }
+}
+
+#[wasm_bindgen]
+pub fn get_audio_sample_count() -> u32 {
+    unsafe {
+        BEVY_APP
+        .as_ref()
+        .unwrap()
+        .world
+        .get_resource::<AudioBuffer>()
+        .expect(
+            "Couldn't find audio buffer resource in bevy app. Did you forget to add UltraPlugin?",
+        )
+        .len() as u32
     }
 }
 
+/// Bytes hosts keep between sessions, for high scores and progress
+///
+/// Only kept by hosts if the rom asks for it with
+/// `#[ultra_bevy::init(save_data = <bytes>)]`, and then only the first that
+/// many bytes. All zeros on the first run. Hosts restore it before the first
+/// update, so it's already filled in when startup systems run.
+#[derive(Default, Deref, DerefMut)]
+pub struct SaveData(pub SaveBuffer<MAX_SAVE_SIZE>);
+
+/// Random numbers, seeded by the host through `init`
+///
+/// Draw all randomness from here, so replays and netplay peers see the same
+/// numbers.
+#[derive(Deref, DerefMut)]
+pub struct UltraRng(pub SmallRng);
+
+impl Default for UltraRng {
+    fn default() -> Self {
+        Self(SmallRng::seed_from_u64(ultra_crustaceous::seed()))
+    }
+}
+
+/// Input of both players for the current frame
+///
+/// Hosts that predate `START` and `SELECT` never press them, so games should
+/// stay playable without them.
 #[derive(Default)]
 pub struct UltraInput {
-    pub p1: ultra_crustaceous::Input,
-    pub p2: ultra_crustaceous::Input,
+    pub p1: Input,
+    pub p2: Input,
+    /// When the buttons of each player were pressed and released
+    pub tracker: InputTracker,
+}
+
+/// Edge queries for both players at once, see [`ButtonState`] for a single
+/// player through [`UltraInput::tracker`]
+///
+/// [`ButtonState`]: ultra_crustaceous::ButtonState
+impl UltraInput {
+    /// Whether either player pressed a button this frame
+    pub fn just_pressed(&self, input: Input) -> bool {
+        self.tracker.p1.just_pressed(input) || self.tracker.p2.just_pressed(input)
+    }
+
+    /// Whether either player released a button this frame
+    pub fn just_released(&self, input: Input) -> bool {
+        self.tracker.p1.just_released(input) || self.tracker.p2.just_released(input)
+    }
+
+    /// The longest either player has held a button for, in frames
+    pub fn held_frames(&self, input: Input) -> u32 {
+        self.tracker
+            .p1
+            .held_frames(input)
+            .max(self.tracker.p2.held_frames(input))
+    }
+
+    /// [`ButtonState::repeat`] for either player
+    ///
+    /// [`ButtonState::repeat`]: ultra_crustaceous::ButtonState::repeat
+    pub fn repeat(&self, input: Input, delay: u32, interval: u32) -> bool {
+        self.tracker.p1.repeat(input, delay, interval)
+            || self.tracker.p2.repeat(input, delay, interval)
+    }
 }
 
 pub struct UltraPlugin;
@@ -67,19 +165,59 @@ impl Plugin for UltraPlugin {
         app.init_resource::<ScreenBuffer>();
         app.init_resource::<PaletteBuffer>();
         app.init_resource::<UltraInput>();
+        app.init_resource::<AudioBuffer>();
+        app.init_resource::<Synth>();
+        app.init_resource::<SaveData>();
+        app.init_resource::<UltraRng>();
     }
 }
 
+/// The app, created on first use
+fn app(app_init: fn() -> App) -> &'static mut App {
+    unsafe { BEVY_APP.get_or_insert_with(|| SendWrapper::new(app_init())) }
+}
+
+/// What `init` does, exported by [`init`]
+///
+/// Hosts call it before anything else, so the app is created with the seed.
+pub fn init_app(seed: u64, app_init: fn() -> App) {
+    ultra_crustaceous::set_seed(seed);
+    app(app_init);
+}
+
+/// What `get_save_buffer_pointer` returns, exported by [`init`] with
+/// `save_data`
+///
+/// Hosts call it before the first update, so it creates the app if needed.
+pub fn save_buffer_pointer(app_init: fn() -> App) -> *const u8 {
+    app(app_init)
+        .world
+        .get_resource::<SaveData>()
+        .expect("Couldn't find save data resource in bevy app. Did you forget to add UltraPlugin?")
+        .as_ptr()
+}
+
 pub fn update_app(p1: u8, p2: u8, app_init: fn() -> App) {
-    let p1 = ultra_crustaceous::Input::from_bits_truncate(p1);
-    let p2 = ultra_crustaceous::Input::from_bits_truncate(p2);
+    let p1 = Input::from_bits_truncate(p1);
+    let p2 = Input::from_bits_truncate(p2);
 
-    let app = unsafe { BEVY_APP.get_or_insert_with(|| SendWrapper::new(app_init())) };
+    let app = app(app_init);
 
     let mut ultra_input = app.world.get_resource_mut::<UltraInput>().expect(
         "Couldn't find output buffer resource in bevy app. Did you forget to add UltraPlugin?",
     );
-    *ultra_input = UltraInput { p1, p2 };
+    ultra_input.p1 = p1;
+    ultra_input.p2 = p2;
+    ultra_input.tracker.update(p1, p2);
 
     app.update();
+
+    // systems play notes on the synth, the frame's samples are rendered once
+    // they've all run
+    app.world.resource_scope(|world, mut synth: Mut<Synth>| {
+        let mut audio = world.get_resource_mut::<AudioBuffer>().expect(
+            "Couldn't find audio buffer resource in bevy app. Did you forget to add UltraPlugin?",
+        );
+        synth.render(audio.frame_mut());
+    });
 }
@@ -2,20 +2,71 @@
 
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{self, parse_macro_input, ItemFn};
+use syn::{self, parse_macro_input, AttributeArgs, ItemFn, Lit, Meta, NestedMeta};
 
 extern crate proc_macro;
 
+/// Exports the rom's functions for a function creating the bevy app
+///
+/// Save data is only exported with `#[ultra_bevy::init(save_data = 16)]`,
+/// giving the number of bytes of `SaveData` hosts keep.
 #[proc_macro_attribute]
-pub fn init(_attr: TokenStream, app_init_function: TokenStream) -> TokenStream {
+pub fn init(attr: TokenStream, app_init_function: TokenStream) -> TokenStream {
     // "fn answer() -> u32 { 42 }".parse().unwrap()
+    let args = parse_macro_input!(attr as AttributeArgs);
     let input = parse_macro_input!(app_init_function as ItemFn);
     let init_app_fn = input.sig.ident.clone();
+
+    let mut save_data = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(arg)) if arg.path.is_ident("save_data") => {
+                match arg.lit {
+                    Lit::Int(size) => save_data = Some(size),
+                    lit => {
+                        return syn::Error::new_spanned(lit, "expected a number of bytes")
+                            .to_compile_error()
+                            .into()
+                    }
+                }
+            }
+            arg => {
+                return syn::Error::new_spanned(arg, "expected `save_data = <bytes>`")
+                    .to_compile_error()
+                    .into()
+            }
+        }
+    }
+
+    let save_exports = save_data.map(|size| {
+        quote! {
+            const _: () = assert!(
+                #size <= ultra_bevy::ultra_crustaceous::MAX_SAVE_SIZE,
+                "save_data is at most MAX_SAVE_SIZE bytes"
+            );
+            #[wasm_bindgen::prelude::wasm_bindgen]
+            pub fn get_save_buffer_pointer() -> *const u8 {
+                ultra_bevy::save_buffer_pointer(|| #init_app_fn())
+            }
+            #[wasm_bindgen::prelude::wasm_bindgen]
+            pub fn get_save_buffer_size() -> u32 {
+                #size
+            }
+        }
+    });
+
     let output = quote! {
+        // named so it doesn't clash with the user's function, which is often
+        // called `init` too
+        #[wasm_bindgen::prelude::wasm_bindgen(js_name = init)]
+        pub fn __ultra_init(seed: u64) {
+            ultra_bevy::init_app(seed, || #init_app_fn());
+        }
         #[wasm_bindgen::prelude::wasm_bindgen]
         pub fn update(p1: u8, p2: u8) {
             ultra_bevy::update_app(p1, p2, || #init_app_fn());
         }
+        #save_exports
         #input
     };
     output.into()
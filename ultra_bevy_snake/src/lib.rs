@@ -5,8 +5,7 @@ use bevy::prelude::*;
 use bevy_system_graph::SystemGraph;
 use derive_more::From;
 use iyes_loopless::prelude::{ConditionHelpers, IntoConditionalSystem};
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use rand::Rng;
 use ultra_bevy::prelude::*;
 
 const SCREEN_SIZE: IVec2 = IVec2::new(ScreenBuffer::WIDTH as i32, ScreenBuffer::HEIGHT as i32);
@@ -51,14 +50,12 @@ struct Apple;
 #[derive(Default, Deref, DerefMut, Clone, Copy)]
 struct Tick(usize);
 
-#[derive(Default)]
-struct Random(Option<SmallRng>);
+/// Apples eaten this game
+#[derive(Default, Deref, DerefMut)]
+struct Score(u32);
 
-impl From<Tick> for SmallRng {
-    fn from(tick: Tick) -> Self {
-        SmallRng::seed_from_u64(*tick as u64)
-    }
-}
+/// Where the high score is kept in the save data
+const HIGH_SCORE_OFFSET: usize = 0;
 
 #[derive(PartialEq, Eq)]
 enum State {
@@ -72,14 +69,14 @@ impl Default for State {
     }
 }
 
-#[ultra_bevy::init()]
+#[ultra_bevy::init(save_data = 4)]
 fn init() -> App {
     let mut app = App::new();
 
     app.add_plugin(UltraPlugin)
         .init_resource::<Tick>()
-        .init_resource::<Random>()
         .init_resource::<State>()
+        .init_resource::<Score>()
         .add_startup_system(setup);
 
     let graph = SystemGraph::new();
@@ -92,9 +89,11 @@ fn init() -> App {
         .then(move_body.run_if(on_step).run_if(alive))
         .then(eat_apples.run_if(on_step).run_if(alive))
         .then(update_body_dir.run_if(on_step).run_if(alive))
+        .then(save_high_score.run_if(on_step))
         .then(draw_background.run_if(on_step))
         .then(draw_apples.run_if(on_step))
-        .then(draw_snake.run_if(on_step));
+        .then(draw_snake.run_if(on_step))
+        .then(draw_scores.run_if(on_step));
 
     app.add_system_set(graph.into());
 
@@ -124,6 +123,28 @@ const WORM_COLOR: u8 = 17;
 const APPLE_COLOR: u8 = 14;
 const APPLE_STEM_COLOR: u8 = 19;
 const DEAD_WORM_COLOR: u8 = 14;
+/// Not in the palette, so it can mark the pixels sprites leave alone
+const TRANSPARENT: u8 = 0xff;
+
+/// A tile, bottom row first since y is up
+#[rustfmt::skip]
+const APPLE: Sprite<u8> = {
+    const T: u8 = TRANSPARENT;
+    const A: u8 = APPLE_COLOR;
+    const S: u8 = APPLE_STEM_COLOR;
+    Sprite::new(TILE_SIZE as i32, TILE_SIZE as i32, &[
+        T, T, T, T, T, T, T, T, T, T,
+        T, T, T, A, A, A, A, T, T, T,
+        T, T, A, A, A, A, A, A, T, T,
+        T, A, A, A, A, A, A, A, A, T,
+        T, A, A, A, A, A, A, A, A, T,
+        T, A, A, A, A, A, A, A, A, T,
+        T, A, A, A, A, A, A, A, A, T,
+        T, T, A, A, A, A, A, A, T, T,
+        T, T, T, A, S, S, A, T, T, T,
+        T, T, T, T, S, S, T, T, T, T,
+    ])
+};
 
 fn setup(
     mut commands: Commands,
@@ -227,12 +248,19 @@ fn eat_apples(
     body_positions: Query<&TilePos, Without<Apple>>,
     mut apples: Query<&mut TilePos, With<Apple>>,
     mut tail: ResMut<Tail>,
-    mut rng: ResMut<Random>,
-    tick: Res<Tick>,
+    mut rng: ResMut<UltraRng>,
+    mut synth: ResMut<Synth>,
+    mut score: ResMut<Score>,
 ) {
     for head_pos in heads.iter() {
         for mut apple_pos in apples.iter_mut() {
             if *apple_pos == *head_pos {
+                **score += 1;
+
+                synth.pulse1.duty = Duty::Quarter;
+                synth.pulse1.envelope = Adsr::PLUCK;
+                synth.pulse1.note_on(note(76));
+
                 let tail_pos = body_positions.get(**tail).unwrap();
 
                 // spawn extra segments for worm on the tail
@@ -243,7 +271,6 @@ fn eat_apples(
                 }
 
                 // move the apple
-                let rng = rng.0.get_or_insert_with(|| SmallRng::from(*tick));
                 loop {
                     **apple_pos = ivec2(rng.gen_range(0..MAP_SIZE.x), rng.gen_range(0..MAP_SIZE.y));
                     // Make sure we don't spawn inside snake
@@ -267,25 +294,23 @@ fn update_body_dir(
     }
 }
 
+fn save_high_score(score: Res<Score>, mut save: ResMut<SaveData>) {
+    if **score > save.read_u32(HIGH_SCORE_OFFSET) {
+        save.write_u32(HIGH_SCORE_OFFSET, **score);
+    }
+}
+
 fn draw_background(mut screen: ResMut<ScreenBuffer>) {
     screen.draw_rect(MAP_POS, MAP_SIZE * TILE_SIZE as i32, MAP_COLOR);
 }
 
 fn draw_apples(apples: Query<&TilePos, With<Apple>>, mut screen: ResMut<ScreenBuffer>) {
+    let options = Blit {
+        transparent: Some(TRANSPARENT),
+        ..Blit::default()
+    };
     for tile in apples.iter() {
-        let pos = tile.to_screen_pos();
-
-        screen.draw_circle(
-            pos.as_vec2() + Vec2::splat(TILE_SIZE as f32 / 2.),
-            4.,
-            APPLE_COLOR,
-        );
-
-        screen.draw_rect(
-            pos + ivec2((TILE_SIZE / 2) as i32 - 1, TILE_SIZE as i32 - 2),
-            ivec2(2, 2),
-            APPLE_STEM_COLOR,
-        );
+        screen.blit(&APPLE, tile.to_screen_pos(), &options);
     }
 }
 
@@ -303,3 +328,20 @@ fn draw_snake(
         tile.draw_filled(screen.as_mut(), color);
     }
 }
+
+/// One pip per apple in the border above the map, the current score over the
+/// high score
+fn draw_scores(score: Res<Score>, save: Res<SaveData>, mut screen: ResMut<ScreenBuffer>) {
+    const PIP_SIZE: i32 = 4;
+    const PIP_SPACING: i32 = 6;
+    const MAX_PIPS: u32 = (MAP_SIZE.x * TILE_SIZE as i32 / PIP_SPACING) as u32;
+
+    let high_score = save.read_u32(HIGH_SCORE_OFFSET);
+    let y = MAP_POS.y + MAP_SIZE.y * TILE_SIZE as i32 + (MAP_POS.y - PIP_SIZE) / 2;
+
+    for i in 0..high_score.max(**score).min(MAX_PIPS) {
+        let color = if i < **score { WORM_COLOR } else { APPLE_COLOR };
+        let pos = ivec2(MAP_POS.x + i as i32 * PIP_SPACING, y);
+        screen.draw_rect(pos, IVec2::splat(PIP_SIZE), color);
+    }
+}
@@ -0,0 +1,7 @@
+use ultra_snapshot::RomTest;
+
+#[test]
+fn first_step() {
+    // nothing but the border is drawn until the first step on the fifth frame
+    RomTest::built().assert_frame(4, "tests/snapshots/first_step.png");
+}
@@ -0,0 +1,414 @@
+//! Records rom frames to animated GIF or APNG files
+//!
+//! Frames are already indexed with at most 32 colors, so they're written with
+//! the rom's own palette, no quantization needed. The clips come out exact and
+//! small enough for READMEs and bug reports.
+//!
+//! ```no_run
+//! use ultra_capture::Recorder;
+//! use ultra_crustaceous::Input;
+//! use ultra_host::Rom;
+//!
+//! let wasm = std::fs::read("dist/ultra_snake/main.wasm").unwrap();
+//! let mut rom = Rom::new(&wasm).unwrap();
+//! let mut recorder = Recorder::new();
+//!
+//! for _ in 0..120 {
+//!     recorder.push(rom.step(Input::RIGHT, Input::empty()).unwrap());
+//! }
+//!
+//! recorder.save("snake.gif").unwrap();
+//! ```
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use thiserror::Error;
+use ultra_crustaceous::{PaletteBuffer, ScreenBuffer};
+use ultra_host::Frame;
+
+const WIDTH: usize = ScreenBuffer::WIDTH;
+const HEIGHT: usize = ScreenBuffer::HEIGHT;
+
+/// Browsers show GIF frames shorter than this many centiseconds for 1/10 s
+const MIN_GIF_DELAY: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Gif(#[from] gif::EncodingError),
+    #[error(transparent)]
+    Png(#[from] png::EncodingError),
+    #[error("no frames recorded")]
+    Empty,
+    #[error("unknown capture format `{0}`, expected gif, png or apng")]
+    UnknownFormat(String),
+}
+
+/// A frame and how many times in a row it was pushed
+struct Captured {
+    /// Palette index of each pixel, rows top to bottom
+    pixels: Vec<u8>,
+    /// Rgb colors of the palette, with black added if any pixel is outside it
+    palette: Vec<u8>,
+    frames: u32,
+}
+
+/// Collects frames, and writes them as an animation
+pub struct Recorder {
+    fps: u32,
+    captured: Vec<Captured>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    /// Records frames shown at 60 fps
+    pub fn new() -> Self {
+        Self {
+            fps: 60,
+            captured: Vec::new(),
+        }
+    }
+
+    /// Frames per second the frames are shown at
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.fps = fps.max(1);
+        self
+    }
+
+    /// Adds a frame to the end of the animation
+    pub fn push(&mut self, frame: Frame) {
+        let (pixels, palette) = indexed(frame.screen, frame.palette);
+
+        if let Some(last) = self.captured.last_mut() {
+            if last.pixels == pixels && last.palette == palette {
+                last.frames += 1;
+                return;
+            }
+        }
+
+        self.captured.push(Captured {
+            pixels,
+            palette,
+            frames: 1,
+        });
+    }
+
+    /// Number of frames pushed
+    pub fn len(&self) -> usize {
+        self.captured
+            .iter()
+            .map(|captured| captured.frames as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captured.is_empty()
+    }
+
+    /// Writes the animation to a file, the format is picked by extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let write = match extension.as_str() {
+            "gif" => Self::write_gif,
+            "png" | "apng" => Self::write_apng,
+            _ => return Err(Error::UnknownFormat(extension)),
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        write(self, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the animation as a looping GIF
+    ///
+    /// GIF delays are in centiseconds, and browsers slow down frames shorter
+    /// than 2 of them, so frames are dropped as needed to stay at or below
+    /// 50 fps. Only the part of each frame that changed is stored.
+    pub fn write_gif(&self, writer: impl Write) -> Result<(), Error> {
+        let first = self.captured.first().ok_or(Error::Empty)?;
+
+        // start of each frame in centiseconds, leaving out frames that
+        // would be shown too briefly
+        let mut shown: Vec<(&Captured, u32)> = Vec::new();
+        let mut frame_number = 0;
+        for captured in &self.captured {
+            let start = self.centiseconds(frame_number);
+            frame_number += captured.frames;
+
+            match shown.last() {
+                Some((_, last_start)) if start - last_start < MIN_GIF_DELAY => {}
+                _ => shown.push((captured, start)),
+            }
+        }
+        let end = self.centiseconds(frame_number);
+
+        let mut encoder = gif::Encoder::new(writer, WIDTH as u16, HEIGHT as u16, &first.palette)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        let mut previous: Option<&Captured> = None;
+        for (index, (captured, start)) in shown.iter().enumerate() {
+            let next_start = shown.get(index + 1).map_or(end, |(_, start)| *start);
+            let delay = (next_start - start).max(MIN_GIF_DELAY);
+
+            let palette_changed = captured.palette != first.palette;
+            let (left, top, width, height) = match previous {
+                Some(previous) if previous.palette == captured.palette => {
+                    changed_rect(&previous.pixels, &captured.pixels)
+                }
+                _ => (0, 0, WIDTH, HEIGHT),
+            };
+
+            let pixels = (top..top + height)
+                .flat_map(|y| &captured.pixels[y * WIDTH + left..y * WIDTH + left + width])
+                .copied()
+                .collect::<Vec<_>>();
+
+            encoder.write_frame(&gif::Frame {
+                left: left as u16,
+                top: top as u16,
+                width: width as u16,
+                height: height as u16,
+                buffer: Cow::Owned(pixels),
+                palette: palette_changed.then(|| captured.palette.clone()),
+                delay: delay.min(u16::MAX as u32) as u16,
+                ..gif::Frame::default()
+            })?;
+
+            previous = Some(captured);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the animation as a looping APNG
+    ///
+    /// APNG delays are fractions of a second, so every frame is kept. If the
+    /// palette changes during the animation, the frames are stored as rgb
+    /// instead of indexed.
+    pub fn write_apng(&self, writer: impl Write) -> Result<(), Error> {
+        let first = self.captured.first().ok_or(Error::Empty)?;
+        let indexed = self
+            .captured
+            .iter()
+            .all(|captured| captured.palette == first.palette);
+
+        let mut encoder = png::Encoder::new(writer, WIDTH as u32, HEIGHT as u32);
+        encoder.set_depth(png::BitDepth::Eight);
+        if indexed {
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_palette(first.palette.clone());
+        } else {
+            encoder.set_color(png::ColorType::Rgb);
+        }
+        encoder.set_animated(self.captured.len() as u32, 0)?;
+
+        let mut writer = encoder.write_header()?;
+        for captured in &self.captured {
+            let (numerator, denominator) = fraction(captured.frames, self.fps);
+            writer.set_frame_delay(numerator, denominator)?;
+
+            if indexed {
+                writer.write_image_data(&captured.pixels)?;
+            } else {
+                let rgb: Vec<u8> = captured
+                    .pixels
+                    .iter()
+                    .flat_map(|index| {
+                        let index = *index as usize * 3;
+                        captured.palette[index..index + 3].iter().copied()
+                    })
+                    .collect();
+                writer.write_image_data(&rgb)?;
+            }
+        }
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    fn centiseconds(&self, frame_number: u32) -> u32 {
+        ((frame_number as u64 * 100 + self.fps as u64 / 2) / self.fps as u64) as u32
+    }
+}
+
+/// Palette indices of a frame flipped to rows top to bottom, and its palette
+/// as rgb
+fn indexed(screen: &ScreenBuffer, palette: &PaletteBuffer) -> (Vec<u8>, Vec<u8>) {
+    let mut rgb: Vec<u8> = palette
+        .iter()
+        .flat_map(|color| {
+            let [_, r, g, b] = color.to_rgb888().to_be_bytes();
+            [r, g, b]
+        })
+        .collect();
+    let colors = palette.len() as u8;
+
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT);
+    for y in (0..HEIGHT).rev() {
+        pixels.extend((0..WIDTH).map(|x| screen.get_pixel(x, y)));
+    }
+
+    // indices outside the palette are undefined, show them as black
+    if pixels.iter().any(|index| *index >= colors) {
+        rgb.extend([0, 0, 0]);
+        pixels
+            .iter_mut()
+            .filter(|index| **index >= colors)
+            .for_each(|index| *index = colors);
+    }
+
+    (pixels, rgb)
+}
+
+/// The smallest rectangle containing all differing pixels, as left, top,
+/// width and height
+///
+/// A single pixel if nothing changed, since GIF frames can't be empty.
+fn changed_rect(a: &[u8], b: &[u8]) -> (usize, usize, usize, usize) {
+    let row_differs = |y: &usize| a[y * WIDTH..(y + 1) * WIDTH] != b[y * WIDTH..(y + 1) * WIDTH];
+    let column_differs = |x: &usize| (0..HEIGHT).any(|y| a[y * WIDTH + x] != b[y * WIDTH + x]);
+
+    let top = match (0..HEIGHT).find(row_differs) {
+        Some(top) => top,
+        None => return (0, 0, 1, 1),
+    };
+    let bottom = (0..HEIGHT).rfind(row_differs).unwrap();
+    let left = (0..WIDTH).find(column_differs).unwrap();
+    let right = (0..WIDTH).rfind(column_differs).unwrap();
+
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+/// `frames / fps` as an APNG delay fraction
+fn fraction(frames: u32, fps: u32) -> (u16, u16) {
+    let divisor = gcd(frames, fps);
+    let (mut numerator, mut denominator) = (frames / divisor, fps / divisor);
+    // close enough for absurdly long frames
+    while numerator > u16::MAX as u32 || denominator > u16::MAX as u32 {
+        numerator = (numerator / 2).max(1);
+        denominator = (denominator / 2).max(1);
+    }
+    (numerator as u16, denominator as u16)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a.max(1)
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ultra_crustaceous::Input;
+    use ultra_host::Rom;
+
+    /// Draws a pixel at x = p1, and fills the screen with p2
+    const ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (memory.fill (i32.const 16) (local.get 1) (i32.const 76800))
+                (i32.store8 (i32.add (i32.const 16) (local.get 0)) (i32.const 3))))
+    "#;
+
+    fn record(inputs: &[(u8, u8)]) -> Recorder {
+        let mut rom = Rom::new(&wat::parse_str(ROM).unwrap()).unwrap();
+        let mut recorder = Recorder::new();
+        for (p1, p2) in inputs {
+            let frame = rom
+                .step(
+                    Input::from_bits_truncate(*p1),
+                    Input::from_bits_truncate(*p2),
+                )
+                .unwrap();
+            recorder.push(frame);
+        }
+        recorder
+    }
+
+    #[test]
+    fn gif_frames() {
+        let recorder = record(&[(0, 0), (0, 0), (0, 0), (1, 0), (2, 0), (3, 0), (4, 40)]);
+        assert_eq!(recorder.len(), 7);
+
+        let mut bytes = Vec::new();
+        recorder.write_gif(&mut bytes).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&bytes[..]).unwrap();
+        assert_eq!(decoder.global_palette().unwrap().len(), 32 * 3);
+
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.left,
+                frame.top,
+                frame.width,
+                frame.delay,
+                frame.buffer.to_vec(),
+            ));
+        }
+
+        // the first three frames are the same, and 60 fps is too fast for
+        // gifs, so the frame at 8 cs is dropped
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].3, 5);
+        assert_eq!(frames[2].3, 3);
+        // only the changed pixels, in the bottom row
+        assert_eq!((frames[1].0, frames[1].1, frames[1].2), (0, 239, 2));
+        assert_eq!(frames[1].4, [0, 3]);
+        // out of palette index turned black
+        assert_eq!(frames[3].2, 320);
+        assert_eq!(frames[3].4[0], 32);
+    }
+
+    #[test]
+    fn apng_frames() {
+        let recorder = record(&[(0, 0), (0, 0), (1, 0)]);
+
+        let mut bytes = Vec::new();
+        recorder.write_apng(&mut bytes).unwrap();
+
+        let mut decoder = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let info = decoder.info();
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert_eq!(info.animation_control.unwrap().num_frames, 2);
+
+        let mut pixels = vec![0; decoder.output_buffer_size()];
+        decoder.next_frame(&mut pixels).unwrap();
+        let delay = decoder.info().frame_control.unwrap();
+        assert_eq!((delay.delay_num, delay.delay_den), (1, 30));
+        assert_eq!(pixels[239 * 320], 3);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(matches!(
+            Recorder::new().write_gif(Vec::new()),
+            Err(Error::Empty)
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! The exports every rom needs, generated from a [`Game`]
+
+use crate::{AudioBuffer, Input, PaletteBuffer, ScreenBuffer, DEFAULT_PALETTE};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A rom's state, turned into a rom with [`ultra_game!`](crate::ultra_game)
+///
+/// The game is created with `Default` the first time a host calls into the
+/// rom, after `init`, so [`seed`](crate::seed) already returns the host's
+/// seed there.
+pub trait Game {
+    /// Advances the game by a frame
+    fn update(&mut self, p1: Input, p2: Input);
+
+    /// What `get_screen_buffer_pointer` points to
+    fn screen(&self) -> &ScreenBuffer;
+
+    /// What `get_palette_buffer_pointer` points to, the default palette unless
+    /// overridden
+    fn palette(&self) -> &PaletteBuffer {
+        &DEFAULT_PALETTE
+    }
+
+    /// Samples of the latest update, only exported with
+    /// `ultra_game!(MyGame, audio)`
+    fn audio(&self) -> Option<&AudioBuffer> {
+        None
+    }
+
+    /// Bytes for hosts to keep between sessions, usually a
+    /// [`SaveBuffer`](crate::SaveBuffer). Only exported with
+    /// `ultra_game!(MyGame, save_data)`, and must stay in the same place
+    fn save_data(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+/// A value in a `static`, created on first use, without `std`, an allocator or
+/// a lock
+///
+/// Roms only run on one thread, so access never has to wait. The cell still
+/// checks that nothing else is using the value, and panics instead of handing
+/// out a second `&mut`, which keeps it sound on threaded targets like the
+/// native builds tests run in.
+pub struct StaticCell<T> {
+    in_use: AtomicBool,
+    value: UnsafeCell<Option<T>>,
+}
+
+// safety: `in_use` makes sure only one `&mut T` exists at a time
+unsafe impl<T: Send> Sync for StaticCell<T> {}
+
+impl<T> StaticCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            in_use: AtomicBool::new(false),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Runs `f` with the value, creating it with `Default` first if needed
+    ///
+    /// Panics if the value is already in use, by a call to `with` from inside
+    /// `f` or on another thread.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Default,
+    {
+        struct Release<'a>(&'a AtomicBool);
+
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Release);
+            }
+        }
+
+        if self.in_use.swap(true, Ordering::Acquire) {
+            panic!("StaticCell is already in use");
+        }
+        let _release = Release(&self.in_use);
+
+        // safety: in_use was false, so there are no other references
+        let value = unsafe { &mut *self.value.get() };
+        f(value.get_or_insert_with(T::default))
+    }
+}
+
+impl<T> Default for StaticCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Generates the exports of a rom from a type implementing [`Game`] and
+/// `Default`
+///
+/// ```ignore
+/// #[derive(Default)]
+/// struct MyGame { screen: ScreenBuffer }
+///
+/// impl Game for MyGame {
+///     fn update(&mut self, p1: Input, p2: Input) { /* ... */ }
+///     fn screen(&self) -> &ScreenBuffer { &self.screen }
+/// }
+///
+/// ultra_game!(MyGame);
+/// ```
+///
+/// `update`, `init`, `get_screen_buffer_pointer` and
+/// `get_palette_buffer_pointer` are always exported. Add `audio` and/or
+/// `save_data` after the type for the exports of [`Game::audio`] and
+/// [`Game::save_data`].
+#[macro_export]
+macro_rules! ultra_game {
+    ($game:ty $(, $feature:ident)* $(,)?) => {
+        const _: () = {
+            static GAME: $crate::StaticCell<$game> = $crate::StaticCell::new();
+
+            #[no_mangle]
+            pub extern "C" fn init(seed: u64) {
+                // before the game is created, so it can use the seed
+                $crate::set_seed(seed);
+                GAME.with(|_| {});
+            }
+
+            #[no_mangle]
+            pub extern "C" fn update(p1: u8, p2: u8) {
+                GAME.with(|game| {
+                    $crate::Game::update(
+                        game,
+                        $crate::Input::from_bits_truncate(p1),
+                        $crate::Input::from_bits_truncate(p2),
+                    )
+                });
+            }
+
+            #[no_mangle]
+            pub extern "C" fn get_screen_buffer_pointer() -> *const u8 {
+                GAME.with(|game| $crate::Game::screen(game).as_ptr())
+            }
+
+            #[no_mangle]
+            pub extern "C" fn get_palette_buffer_pointer() -> *const u8 {
+                GAME.with(|game| $crate::Game::palette(game).as_bytes().as_ptr())
+            }
+
+            $($crate::ultra_game!(@export $feature);)*
+        };
+    };
+
+    (@export audio) => {
+        #[no_mangle]
+        pub extern "C" fn get_audio_buffer_pointer() -> *const i16 {
+            GAME.with(|game| {
+                $crate::Game::audio(game).map_or(core::ptr::null(), |audio| audio.as_ptr())
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_audio_sample_count() -> u32 {
+            GAME.with(|game| $crate::Game::audio(game).map_or(0, |audio| audio.len() as u32))
+        }
+    };
+
+    (@export save_data) => {
+        #[no_mangle]
+        pub extern "C" fn get_save_buffer_pointer() -> *const u8 {
+            GAME.with(|game| {
+                $crate::Game::save_data(game).map_or(core::ptr::null(), |save| save.as_ptr())
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C" fn get_save_buffer_size() -> u32 {
+            GAME.with(|game| $crate::Game::save_data(game).map_or(0, |save| save.len() as u32))
+        }
+    };
+}
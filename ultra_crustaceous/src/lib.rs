@@ -1,14 +1,63 @@
 #![no_std]
 
 use bitflags::bitflags;
+use core::sync::atomic::{AtomicU64, Ordering};
 use derive_more::{Deref, DerefMut};
 
+mod game;
+pub use game::{Game, StaticCell};
+
 /// Number of colors in the palette buffer
 pub const PALETTE_COLORS: usize = 32;
 
+/// Seed roms use when hosts don't call `init`
+pub const DEFAULT_SEED: u64 = 0;
+
+static SEED: AtomicU64 = AtomicU64::new(DEFAULT_SEED);
+
+/// Stores the seed hosts pass to `init`, call it from the rom's `init` export
+pub fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+}
+
+/// The seed passed to `init`, or [`DEFAULT_SEED`] if the host didn't call it
+///
+/// Seed all random number generators with this, so replays and netplay run
+/// the same on every host.
+pub fn seed() -> u64 {
+    SEED.load(Ordering::Relaxed)
+}
+
+/// Most bytes of save data a rom may ask hosts to keep
+pub const MAX_SAVE_SIZE: usize = 4096;
+
+/// Palette used by hosts when a rom doesn't export `get_palette_buffer_pointer`
+///
+/// https://lospec.com/palette-list/dawnbringer-32
+pub const DEFAULT_PALETTE: PaletteBuffer = {
+    const DAWNBRINGER_32: [u32; PALETTE_COLORS] = [
+        0x000000, 0x222034, 0x45283c, 0x663931, 0x8f563b, 0xdf7126, 0xd9a066, 0xeec39a, 0xfbf236,
+        0x99e550, 0x6abe30, 0x37946e, 0x4b692f, 0x524b24, 0x323c39, 0x3f3f74, 0x306082, 0x5b6ee1,
+        0x639bff, 0x5fcde4, 0xcbdbfc, 0xffffff, 0x9badb7, 0x847e87, 0x696a6a, 0x595652, 0x76428a,
+        0xac3232, 0xd95763, 0xd77bba, 0x8f974a, 0x8a6f30,
+    ];
+
+    let mut colors = [Color(0, 0); PALETTE_COLORS];
+    let mut i = 0;
+    while i < PALETTE_COLORS {
+        colors[i] = Color::from_rgb(DAWNBRINGER_32[i]);
+        i += 1;
+    }
+    PaletteBuffer(colors)
+};
+
+/// Palette index of every pixel, row by row, with y pointing up
 #[derive(Deref, DerefMut)]
+#[repr(transparent)]
 pub struct ScreenBuffer(pub [u8; Self::NUM_PIXELS]);
 
+const _: () = assert!(core::mem::size_of::<ScreenBuffer>() == 76_800);
+
 impl Default for ScreenBuffer {
     fn default() -> Self {
         ScreenBuffer([0; Self::NUM_PIXELS])
@@ -41,6 +90,21 @@ impl ScreenBuffer {
         let i = x + y * Self::WIDTH;
         self[i]
     }
+
+    /// The buffer as the bytes `get_screen_buffer_pointer` points to
+    pub fn as_bytes(&self) -> &[u8; Self::NUM_PIXELS] {
+        &self.0
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; Self::NUM_PIXELS] {
+        &mut self.0
+    }
+
+    /// Views bytes in the screen buffer layout as a screen buffer
+    pub fn from_bytes(bytes: &[u8; Self::NUM_PIXELS]) -> &Self {
+        // safety: ScreenBuffer is a transparent wrapper around the same array
+        unsafe { &*(bytes as *const [u8; Self::NUM_PIXELS] as *const Self) }
+    }
 }
 
 #[cfg(feature = "rastateur")]
@@ -68,26 +132,281 @@ impl rastateur::PixelBuffer<u8> for ScreenBuffer {
     }
 }
 
-#[derive(Deref, DerefMut)]
+/// Sound samples for a frame, pointed to by `get_audio_buffer_pointer`
+///
+/// Samples are signed 16-bit mono at [`AudioBuffer::SAMPLE_RATE`], and
+/// `get_audio_sample_count` returns how many of them the latest update wrote.
+#[repr(C)]
+pub struct AudioBuffer {
+    samples: [i16; Self::CAPACITY],
+    len: usize,
+}
+
+impl Default for AudioBuffer {
+    fn default() -> Self {
+        AudioBuffer {
+            samples: [0; Self::CAPACITY],
+            len: 0,
+        }
+    }
+}
+
+impl AudioBuffer {
+    /// Samples per second
+    pub const SAMPLE_RATE: usize = 44_100;
+
+    /// Samples played during a single frame at 60 fps
+    pub const SAMPLES_PER_FRAME: usize = Self::SAMPLE_RATE / 60;
+
+    /// Maximum number of samples a rom may return for a frame
+    pub const CAPACITY: usize = 1024;
+
+    /// Removes all samples
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends a sample, returns `false` if the buffer is full
+    pub fn push(&mut self, sample: i16) -> bool {
+        if self.len == Self::CAPACITY {
+            return false;
+        }
+        self.samples[self.len] = sample;
+        self.len += 1;
+        true
+    }
+
+    /// Replaces the samples with a frame's worth of silence, and returns them
+    /// for writing into
+    pub fn frame_mut(&mut self) -> &mut [i16] {
+        self.len = Self::SAMPLES_PER_FRAME;
+        let samples = &mut self.samples[..Self::SAMPLES_PER_FRAME];
+        samples.fill(0);
+        samples
+    }
+
+    /// The samples written so far
+    pub fn samples(&self) -> &[i16] {
+        &self.samples[..self.len]
+    }
+
+    /// Number of samples, what `get_audio_sample_count` should return
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Pointer to the first sample, what `get_audio_buffer_pointer` should
+    /// return
+    pub fn as_ptr(&self) -> *const i16 {
+        self.samples.as_ptr()
+    }
+}
+
+/// Bytes hosts keep between sessions, pointed to by `get_save_buffer_pointer`
+///
+/// `N` is what `get_save_buffer_size` should return, at most
+/// [`MAX_SAVE_SIZE`]. The buffer is all zeros until hosts restore the data of
+/// an earlier session into it, before the first update.
+#[derive(Clone, Copy, PartialEq, Eq, Deref, DerefMut)]
+#[repr(transparent)]
+pub struct SaveBuffer<const N: usize>([u8; N]);
+
+impl<const N: usize> Default for SaveBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> SaveBuffer<N> {
+    /// Size of the buffer in bytes, fails to compile if it's too large
+    pub const SIZE: usize = {
+        assert!(
+            N <= MAX_SAVE_SIZE,
+            "save buffers are at most MAX_SAVE_SIZE bytes"
+        );
+        N
+    };
+
+    pub const fn new() -> Self {
+        // evaluating SIZE checks N
+        let _ = Self::SIZE;
+        Self([0; N])
+    }
+
+    /// Whether nothing was ever saved, or a host didn't restore it
+    pub fn is_blank(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+
+    /// Reads a little endian `u32` at a byte offset
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.0[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Writes a little endian `u32` at a byte offset
+    pub fn write_u32(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Pointer to the first byte, what `get_save_buffer_pointer` should return
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0.as_ptr()
+    }
+}
+
+/// Colors the palette indices in the screen buffer refer to
+#[derive(Clone, Copy, Deref, DerefMut)]
+#[repr(transparent)]
 pub struct PaletteBuffer([Color; PALETTE_COLORS]);
 
+const _: () = assert!(core::mem::size_of::<PaletteBuffer>() == PaletteBuffer::NUM_BYTES);
+const _: () = assert!(core::mem::align_of::<PaletteBuffer>() == 1);
+
 impl Default for PaletteBuffer {
     fn default() -> Self {
         PaletteBuffer([Color(0, 0); PALETTE_COLORS])
     }
 }
 
+impl PaletteBuffer {
+    /// Size of the palette buffer in bytes, two for each color
+    pub const NUM_BYTES: usize = PALETTE_COLORS * 2;
+
+    /// The buffer as the bytes `get_palette_buffer_pointer` points to
+    pub fn as_bytes(&self) -> &[u8; Self::NUM_BYTES] {
+        // safety: PaletteBuffer is a transparent wrapper around an array of
+        // colors, which are two bytes each without padding
+        unsafe { &*(self as *const Self as *const [u8; Self::NUM_BYTES]) }
+    }
+
+    /// The buffer as mutable bytes
+    ///
+    /// Nothing stops the reserved bits of the colors from being set through
+    /// this, but [`Color`] ignores them everywhere.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8; Self::NUM_BYTES] {
+        // safety: as above, and every bit pattern is a valid color
+        unsafe { &mut *(self as *mut Self as *mut [u8; Self::NUM_BYTES]) }
+    }
+
+    /// Views bytes in the palette buffer layout as a palette buffer
+    ///
+    /// Like with [`as_bytes_mut`](Self::as_bytes_mut), the reserved bits may be
+    /// set, and are ignored.
+    pub fn from_bytes(bytes: &[u8; Self::NUM_BYTES]) -> &Self {
+        // safety: as above
+        unsafe { &*(bytes as *const [u8; Self::NUM_BYTES] as *const Self) }
+    }
+}
+
 // rename to palette color?
+/// A 12-bit color, as stored in the palette buffer
+///
+/// The two bytes are `0000rrrr` and `ggggbbbb`, the upper four bits of the
+/// first byte are reserved. Colors created here always have them zero, and
+/// where they're set anyway, through the byte views of [`PaletteBuffer`],
+/// they're ignored, including when comparing and hashing colors.
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct Color(u8, u8);
 
+impl PartialEq for Color {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_u16() == other.to_u16()
+    }
+}
+
+impl Eq for Color {}
+
+impl core::hash::Hash for Color {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.to_u16().hash(state);
+    }
+}
+
+const _: () = assert!(core::mem::size_of::<Color>() == 2);
+
 impl Color {
+    /// Creates a color from 24-bit rgb, `0xrrggbb`, dropping the lower bits of
+    /// each channel
     pub const fn from_rgb(rgb: u32) -> Self {
         let b = ((rgb & 0xff) >> 4) as u8;
         let g = (((rgb & 0xff00) >> 8) >> 4) as u8;
         let r = (((rgb & 0xff0000) >> 16) >> 4) as u8;
         Color(r, (g << 4) | b)
     }
+
+    /// Creates a color from 4-bit channels, higher bits are ignored
+    pub const fn from_rgb4(r: u8, g: u8, b: u8) -> Self {
+        Color(r & 0xf, (g & 0xf) << 4 | (b & 0xf))
+    }
+
+    /// Creates a color from the two bytes stored in the palette buffer,
+    /// `(0000rrrr, ggggbbbb)`
+    ///
+    /// The reserved bits are ignored.
+    pub const fn from_bytes(bytes: [u8; 2]) -> Self {
+        Color(bytes[0] & 0xf, bytes[1])
+    }
+
+    /// The two bytes stored in the palette buffer for this color, with the
+    /// reserved bits zero
+    pub const fn to_bytes(self) -> [u8; 2] {
+        [self.r(), self.1]
+    }
+
+    /// Creates a color from `0x0rgb`, the reserved bits are ignored
+    pub const fn from_u16(rgb: u16) -> Self {
+        Self::from_bytes(rgb.to_be_bytes())
+    }
+
+    /// The color as `0x0rgb`
+    pub const fn to_u16(self) -> u16 {
+        u16::from_be_bytes([self.r(), self.1])
+    }
+
+    /// Red, from 0 to 15
+    pub const fn r(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    /// Green, from 0 to 15
+    pub const fn g(self) -> u8 {
+        self.1 >> 4
+    }
+
+    /// Blue, from 0 to 15
+    pub const fn b(self) -> u8 {
+        self.1 & 0xf
+    }
+
+    /// The color as 24-bit rgb, `0xrrggbb`
+    ///
+    /// Each channel's nibble is repeated, so 0xf becomes 0xff, and white stays
+    /// white.
+    pub const fn to_rgb888(self) -> u32 {
+        let [r, g, b] = [self.r() as u32, self.g() as u32, self.b() as u32];
+        (r * 0x11) << 16 | (g * 0x11) << 8 | (b * 0x11)
+    }
+
+    /// The color as 16-bit rgb, 5 bits red, 6 green and 5 blue
+    pub const fn to_rgb565(self) -> u16 {
+        let r = (self.r() << 1 | self.r() >> 3) as u16;
+        let g = (self.g() << 2 | self.g() >> 2) as u16;
+        let b = (self.b() << 1 | self.b() >> 3) as u16;
+        r << 11 | g << 5 | b
+    }
+}
+
+impl core::fmt::Debug for Color {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Color(#{:03x})", self.to_u16())
+    }
 }
 
 bitflags! {
@@ -99,6 +418,10 @@ bitflags! {
         const RIGHT = 1 << 3;
         const BUTTON_1 = 1 << 4;
         const BUTTON_2 = 1 << 5;
+        /// For pausing and menus
+        const START = 1 << 6;
+        /// For picking options, like the number of players
+        const SELECT = 1 << 7;
     }
 }
 
@@ -119,3 +442,286 @@ impl Input {
         }
     }
 }
+
+/// Edge detection for the input of both players
+///
+/// Roms only get the buttons held each frame, call [`InputTracker::update`]
+/// with them at the start of every update to also know when they were pressed
+/// and released.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputTracker {
+    pub p1: ButtonState,
+    pub p2: ButtonState,
+}
+
+impl InputTracker {
+    pub fn update(&mut self, p1: Input, p2: Input) {
+        self.p1.update(p1);
+        self.p2.update(p2);
+    }
+}
+
+/// The buttons of one player, and how long they've been held
+///
+/// Queries taking several buttons answer whether any of them qualifies, so
+/// `just_pressed(Input::BUTTON_1 | Input::START)` is true when either was
+/// pressed. [`ButtonState::held_frames`] counts the one held the longest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ButtonState {
+    current: Input,
+    previous: Input,
+    /// Frames each button has been held for, by bit
+    held: [u32; 8],
+}
+
+impl ButtonState {
+    pub fn update(&mut self, input: Input) {
+        self.previous = self.current;
+        self.current = input;
+        for (bit, held) in self.held.iter_mut().enumerate() {
+            *held = match input.bits() & (1 << bit) {
+                0 => 0,
+                _ => held.saturating_add(1),
+            };
+        }
+    }
+
+    /// The buttons held this frame
+    pub fn current(&self) -> Input {
+        self.current
+    }
+
+    pub fn pressed(&self, input: Input) -> bool {
+        self.current.intersects(input)
+    }
+
+    /// Whether a button went down this frame
+    pub fn just_pressed(&self, input: Input) -> bool {
+        (self.current - self.previous).intersects(input)
+    }
+
+    /// Whether a button went up this frame
+    pub fn just_released(&self, input: Input) -> bool {
+        (self.previous - self.current).intersects(input)
+    }
+
+    /// Number of frames a button has been held for, counting this one, 0 if
+    /// it isn't held
+    pub fn held_frames(&self, input: Input) -> u32 {
+        self.held
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| input.bits() & (1 << bit) != 0)
+            .map(|(_, held)| *held)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Like holding a key in a text field: true when a button is pressed,
+    /// then every `interval` frames once it's been held for `delay` more
+    ///
+    /// Meant for moving through menus.
+    pub fn repeat(&self, input: Input, delay: u32, interval: u32) -> bool {
+        let fires = |held: u32| match held.checked_sub(1 + delay) {
+            _ if held == 1 => true,
+            Some(repeated) => repeated.is_multiple_of(interval.max(1)),
+            None => false,
+        };
+        self.held
+            .iter()
+            .enumerate()
+            .any(|(bit, held)| input.bits() & (1 << bit) != 0 && fires(*held))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channels() {
+        let color = Color::from_rgb(0xf08a3c);
+        assert_eq!((color.r(), color.g(), color.b()), (0xf, 0x8, 0x3));
+        assert_eq!(color, Color::from_rgb4(0xf, 0x8, 0x3));
+        assert_eq!(color.to_u16(), 0x0f83);
+        assert_eq!(Color::from_u16(0x0f83), color);
+    }
+
+    #[test]
+    fn rgb888_replicates_nibbles() {
+        assert_eq!(Color::from_rgb(0xffffff).to_rgb888(), 0xffffff);
+        assert_eq!(Color::from_rgb4(0x1, 0x8, 0x0).to_rgb888(), 0x118800);
+        assert_eq!(Color::from_rgb(0xffffff).to_rgb565(), 0xffff);
+        assert_eq!(Color::from_rgb4(0xf, 0, 0).to_rgb565(), 0xf800);
+    }
+
+    #[test]
+    fn byte_views() {
+        let mut palette = PaletteBuffer::default();
+        palette[1] = Color::from_rgb4(0x1, 0x2, 0x3);
+        assert_eq!(palette.as_bytes()[2..4], [0x01, 0x23]);
+
+        palette.as_bytes_mut()[4..6].copy_from_slice(&[0x0f, 0x80]);
+        assert_eq!(palette[2], Color::from_rgb(0xff8800));
+
+        let bytes = *palette.as_bytes();
+        assert_eq!(PaletteBuffer::from_bytes(&bytes)[1], palette[1]);
+
+        let mut bytes = [0; ScreenBuffer::NUM_PIXELS];
+        bytes[ScreenBuffer::WIDTH + 2] = 7;
+        assert_eq!(ScreenBuffer::from_bytes(&bytes).get_pixel(2, 1), 7);
+    }
+
+    #[test]
+    fn save_buffer() {
+        let mut save = SaveBuffer::<16>::new();
+        assert!(save.is_blank());
+        assert_eq!(SaveBuffer::<16>::SIZE, 16);
+
+        save.write_u32(4, 0x1234_5678);
+        assert!(!save.is_blank());
+        assert_eq!(save.read_u32(4), 0x1234_5678);
+        assert_eq!(save[4..8], [0x78, 0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn audio_buffer() {
+        let mut audio = AudioBuffer::default();
+        assert!(audio.is_empty());
+
+        audio.frame_mut()[0] = 100;
+        assert_eq!(audio.len(), 735);
+        assert_eq!(audio.samples()[..2], [100, 0]);
+
+        audio.clear();
+        assert!((0..AudioBuffer::CAPACITY).all(|_| audio.push(1)));
+        assert!(!audio.push(1));
+        assert_eq!(audio.samples().len(), AudioBuffer::CAPACITY);
+    }
+
+    #[test]
+    fn input_bits() {
+        assert_eq!(Input::all().bits(), 0xff);
+        assert_eq!(
+            Input::from_bits_truncate(0xc0),
+            Input::START | Input::SELECT
+        );
+        // start and select don't move anything
+        assert_eq!((Input::START.x(), Input::SELECT.y()), (0, 0));
+    }
+
+    #[test]
+    fn input_edges() {
+        let mut tracker = InputTracker::default();
+
+        tracker.update(Input::UP, Input::empty());
+        assert!(tracker.p1.just_pressed(Input::UP));
+        assert!(!tracker.p2.just_pressed(Input::UP));
+
+        tracker.update(Input::UP | Input::START, Input::empty());
+        assert!(!tracker.p1.just_pressed(Input::UP));
+        assert!(tracker.p1.just_pressed(Input::UP | Input::START));
+        assert_eq!(tracker.p1.held_frames(Input::UP), 2);
+        assert_eq!(tracker.p1.held_frames(Input::START), 1);
+        assert_eq!(tracker.p1.held_frames(Input::DOWN), 0);
+
+        tracker.update(Input::START, Input::empty());
+        assert!(tracker.p1.just_released(Input::UP));
+        assert!(!tracker.p1.just_released(Input::START));
+        assert!(tracker.p1.pressed(Input::START));
+        assert_eq!(tracker.p1.held_frames(Input::UP), 0);
+    }
+
+    #[test]
+    fn input_repeat() {
+        let mut state = ButtonState::default();
+        for frame in 1..=12 {
+            state.update(Input::DOWN);
+            // once on the press, then every 3 frames after waiting 4
+            let expected = [1, 5, 8, 11].contains(&frame);
+            assert_eq!(state.repeat(Input::DOWN, 4, 3), expected, "frame {frame}");
+        }
+
+        state.update(Input::empty());
+        state.update(Input::DOWN);
+        assert!(state.repeat(Input::DOWN, 4, 3));
+    }
+
+    #[derive(Default)]
+    struct TestGame {
+        screen: ScreenBuffer,
+        save: SaveBuffer<16>,
+    }
+
+    impl Game for TestGame {
+        fn update(&mut self, p1: Input, p2: Input) {
+            self.screen[0] = p1.bits() + p2.bits();
+            self.save.write_u32(0, seed() as u32);
+        }
+
+        fn screen(&self) -> &ScreenBuffer {
+            &self.screen
+        }
+
+        fn save_data(&self) -> Option<&[u8]> {
+            Some(&*self.save)
+        }
+    }
+
+    ultra_game!(TestGame, save_data);
+
+    // the exports, as hosts see them
+    extern "C" {
+        fn init(seed: u64);
+        fn update(p1: u8, p2: u8);
+        fn get_screen_buffer_pointer() -> *const u8;
+        fn get_palette_buffer_pointer() -> *const u8;
+        fn get_save_buffer_pointer() -> *const u8;
+        fn get_save_buffer_size() -> u32;
+    }
+
+    #[test]
+    fn game_exports() {
+        unsafe {
+            init(7);
+            update(1, 2);
+            assert_eq!(*get_screen_buffer_pointer(), 3);
+            assert_eq!(*get_palette_buffer_pointer(), DEFAULT_PALETTE.as_bytes()[0]);
+            assert_eq!(get_save_buffer_size(), 16);
+            assert_eq!(*get_save_buffer_pointer(), 7);
+        }
+    }
+
+    #[test]
+    fn static_cell() {
+        static CELL: StaticCell<u32> = StaticCell::new();
+        CELL.with(|value| *value += 1);
+        assert_eq!(CELL.with(|value| *value), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already in use")]
+    fn static_cell_is_exclusive() {
+        static CELL: StaticCell<u32> = StaticCell::new();
+        CELL.with(|_| CELL.with(|_| {}));
+    }
+
+    #[test]
+    fn reserved_bits_stay_zero() {
+        assert_eq!(Color::from_bytes([0xff, 0x12]).to_bytes(), [0x0f, 0x12]);
+        assert_eq!(Color::from_u16(0xabcd).to_u16(), 0x0bcd);
+        assert_eq!(Color::from_rgb4(0xff, 0xff, 0xff).to_u16(), 0x0fff);
+        assert_eq!(Color::from_rgb(0xffffffff).to_u16(), 0x0fff);
+
+        let mut palette = PaletteBuffer::default();
+        palette.as_bytes_mut()[0] = 0xf3;
+        assert_eq!(palette[0].r(), 0x3);
+        assert_eq!(palette[0].to_u16(), 0x0300);
+        assert_eq!(palette[0].to_bytes(), [0x03, 0x00]);
+        assert_eq!(palette[0], Color::from_rgb4(0x3, 0, 0));
+
+        let mut bytes = [0; PaletteBuffer::NUM_BYTES];
+        bytes[0] = 0x53;
+        assert_eq!(PaletteBuffer::from_bytes(&bytes)[0], palette[0]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Runs a rom without a window, optionally dumping frames to png files
+//!
+//! ```text
+//! cargo run -p ultra_headless -- dist/ultra_snake/main.wasm \
+//!     --frames 600 --script snake.txt --dump 10,300,599 --out frames --capture snake.gif
+//! ```
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use script::Script;
+use std::{
+    fs::{self, create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use ultra_capture::Recorder;
+use ultra_crustaceous::{AudioBuffer, ScreenBuffer, DEFAULT_SEED};
+use ultra_host::{hash_rom, Frame, Rom, SaveState};
+use ultra_meta::Metadata;
+use ultra_movie::Movie;
+use ultra_package::Package;
+
+mod script;
+
+#[derive(clap::Parser)]
+struct Opt {
+    #[clap(long = "log", default_value = "Info")]
+    log_level: log::LevelFilter,
+    /// The rom's wasm module, or an `.ultra.zip` package
+    rom: PathBuf,
+    /// Number of frames to run
+    #[clap(long, default_value = "60")]
+    frames: usize,
+    /// Input script, see `script.rs` for the format
+    #[clap(long, conflicts_with = "movie")]
+    script: Option<PathBuf>,
+    /// Movie to take input from, see `ultra_movie` for the format
+    #[clap(long)]
+    movie: Option<PathBuf>,
+    /// Seed passed to the rom's `init`, movies use the one they were
+    /// recorded with
+    #[clap(long, conflicts_with = "movie")]
+    seed: Option<u64>,
+    /// Records the input of the run to a movie file
+    #[clap(long)]
+    record: Option<PathBuf>,
+    /// File keeping the rom's save data between runs, restored before the
+    /// first frame and updated after the last one
+    #[clap(long)]
+    save_data: Option<PathBuf>,
+    /// Save state to restore before running
+    #[clap(long)]
+    load_state: Option<PathBuf>,
+    /// Saves the state of the rom after the last frame
+    #[clap(long)]
+    save_state: Option<PathBuf>,
+    /// Frames to save as png files
+    #[clap(long, use_value_delimiter = true)]
+    dump: Vec<usize>,
+    /// Records the run to an animated gif or png, picked by extension
+    #[clap(long)]
+    capture: Option<PathBuf>,
+    /// Frame to start the capture at
+    #[clap(long, default_value = "0")]
+    capture_start: usize,
+    /// Saves the rom's audio to a wav file
+    #[clap(long)]
+    audio: Option<PathBuf>,
+    /// Directory to save frames in
+    #[clap(long, default_value = ".")]
+    out: PathBuf,
+}
+
+fn main() -> Result<()> {
+    let opt: Opt = clap::Parser::parse();
+
+    env_logger::builder()
+        .filter(Some("ultra_headless"), opt.log_level)
+        .init();
+
+    let is_package = opt
+        .rom
+        .extension()
+        .is_some_and(|extension| extension == "zip");
+    let wasm = if is_package {
+        let package = Package::open(&opt.rom)
+            .with_context(|| format!("invalid package {}", opt.rom.display()))?;
+        package.wasm().to_vec()
+    } else {
+        fs::read(&opt.rom).with_context(|| format!("couldn't read rom {}", opt.rom.display()))?
+    };
+
+    let movie = match &opt.movie {
+        Some(path) => {
+            let movie = Movie::read(File::open(path)?)
+                .with_context(|| format!("invalid movie {}", path.display()))?;
+            if movie.rom_hash != hash_rom(&wasm) {
+                bail!("{} was recorded with a different rom", path.display());
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+
+    let seed = match &movie {
+        Some(movie) => movie.seed,
+        None => opt.seed.unwrap_or(DEFAULT_SEED),
+    };
+    let mut rom = Rom::with_seed(&wasm, seed)?;
+
+    match Metadata::read(&wasm) {
+        Ok(Some(metadata)) => info!("running {} {}", metadata.title, metadata.version),
+        Ok(None) => {}
+        Err(e) => warn!("ignoring invalid metadata: {e}"),
+    }
+
+    if let Some(path) = &opt.save_data {
+        if !rom.has_save_data() {
+            warn!("rom has no save data, {} won't be used", path.display());
+        } else if path.exists() {
+            info!("loading save data {}", path.display());
+            rom.load_save_data(&fs::read(path)?)
+                .with_context(|| format!("invalid save data {}", path.display()))?;
+        }
+    }
+
+    if let Some(path) = &opt.load_state {
+        info!("loading state {}", path.display());
+        let state = SaveState::read(File::open(path)?)
+            .with_context(|| format!("invalid save state {}", path.display()))?;
+        rom.load_state(&state)?;
+    }
+
+    let script = match &opt.script {
+        Some(path) => Script::parse(&fs::read_to_string(path)?)
+            .with_context(|| format!("invalid script {}", path.display()))?,
+        None => Script::default(),
+    };
+
+    let mut recording = Movie::new(rom.hash(), rom.seed());
+    let mut capture = Recorder::new();
+    let mut audio = Vec::new();
+
+    if !opt.dump.is_empty() {
+        create_dir_all(&opt.out)?;
+    }
+
+    for frame_number in 0..opt.frames {
+        let (p1, p2) = match &movie {
+            Some(movie) => movie.input(frame_number).unwrap_or_default(),
+            None => script.input(frame_number),
+        };
+        recording.push(p1, p2);
+
+        let frame = rom
+            .step(p1, p2)
+            .with_context(|| format!("frame {frame_number}"))?;
+
+        audio.extend_from_slice(frame.audio);
+
+        if opt.capture.is_some() && frame_number >= opt.capture_start {
+            capture.push(frame);
+        }
+
+        if opt.dump.contains(&frame_number) {
+            let path = opt.out.join(format!("frame_{frame_number:05}.png"));
+            info!("saving {}", path.display());
+            save_png(&frame, &path)?;
+        }
+    }
+
+    info!("ran {} frames", opt.frames);
+
+    if let (Some(path), Some(data)) = (&opt.save_data, rom.save_data()) {
+        info!("saving save data {}", path.display());
+        fs::write(path, data)?;
+    }
+
+    if let Some(path) = &opt.save_state {
+        info!("saving state {}", path.display());
+        rom.save_state()?
+            .write(BufWriter::new(File::create(path)?))?;
+    }
+
+    if let Some(path) = &opt.capture {
+        info!("saving capture {}", path.display());
+        capture
+            .save(path)
+            .with_context(|| format!("couldn't save capture {}", path.display()))?;
+    }
+
+    if let Some(path) = &opt.audio {
+        if !rom.has_audio() {
+            warn!("rom has no audio, saving silence");
+        }
+        info!("saving audio {}", path.display());
+        save_wav(&audio, path)?;
+    }
+
+    if let Some(path) = &opt.record {
+        info!("saving movie {}", path.display());
+        recording.write(BufWriter::new(File::create(path)?))?;
+    }
+
+    Ok(())
+}
+
+fn save_png(frame: &Frame, path: &Path) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(
+        file,
+        ScreenBuffer::WIDTH as u32,
+        ScreenBuffer::HEIGHT as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.to_rgb8())?;
+
+    Ok(())
+}
+
+/// Writes 16-bit mono samples at the spec's sample rate
+fn save_wav(samples: &[i16], path: &Path) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let data_size = samples.len() as u32 * 2;
+    let sample_rate = AudioBuffer::SAMPLE_RATE as u32;
+
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // pcm
+    file.write_all(&1u16.to_le_bytes())?; // channels
+    file.write_all(&sample_rate.to_le_bytes())?;
+    file.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    file.write_all(&2u16.to_le_bytes())?; // bytes per sample
+    file.write_all(&16u16.to_le_bytes())?; // bits per sample
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        file.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(file.flush()?)
+}
@@ -0,0 +1,142 @@
+//! Timed input scripts
+//!
+//! A script is a text file where each line sets the input of both players from
+//! a given frame and onwards, until the next line takes over:
+//!
+//! ```text
+//! # frame  p1              p2
+//! 0        -               -
+//! 10       RIGHT           -
+//! 40       UP|BUTTON_1     LEFT
+//! ```
+//!
+//! Frames are counted from 0, and must be in increasing order. Inputs are
+//! either `-` for no buttons, `|`-separated button names or a raw number.
+
+use anyhow::{bail, Context, Result};
+use ultra_crustaceous::Input;
+
+#[derive(Default)]
+pub struct Script {
+    /// Frame the input starts at, p1, p2
+    entries: Vec<(usize, Input, Input)>,
+}
+
+impl Script {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut entries: Vec<(usize, Input, Input)> = Vec::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let entry = parse_entry(line).with_context(|| format!("line {}", line_index + 1))?;
+
+            if let Some((previous_frame, ..)) = entries.last() {
+                if entry.0 <= *previous_frame {
+                    bail!(
+                        "line {}: frame {} is not after frame {previous_frame}",
+                        line_index + 1,
+                        entry.0
+                    );
+                }
+            }
+
+            entries.push(entry);
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// The input of both players at the given frame
+    pub fn input(&self, frame: usize) -> (Input, Input) {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(start, ..)| *start <= frame)
+            .map_or((Input::empty(), Input::empty()), |(_, p1, p2)| (*p1, *p2))
+    }
+}
+
+fn parse_entry(line: &str) -> Result<(usize, Input, Input)> {
+    let mut columns = line.split_whitespace();
+    let (Some(frame), Some(p1), Some(p2), None) =
+        (columns.next(), columns.next(), columns.next(), columns.next())
+    else {
+        bail!("expected three columns: frame, p1 and p2");
+    };
+
+    let frame = frame
+        .parse()
+        .with_context(|| format!("invalid frame number `{frame}`"))?;
+
+    Ok((frame, parse_input(p1)?, parse_input(p2)?))
+}
+
+fn parse_input(input: &str) -> Result<Input> {
+    if input == "-" {
+        return Ok(Input::empty());
+    }
+
+    if let Ok(bits) = input.parse() {
+        return Ok(Input::from_bits_truncate(bits));
+    }
+
+    input.split('|').try_fold(Input::empty(), |input, name| {
+        let button = match name {
+            "UP" => Input::UP,
+            "DOWN" => Input::DOWN,
+            "LEFT" => Input::LEFT,
+            "RIGHT" => Input::RIGHT,
+            "BUTTON_1" => Input::BUTTON_1,
+            "BUTTON_2" => Input::BUTTON_2,
+            "START" => Input::START,
+            "SELECT" => Input::SELECT,
+            _ => bail!("unknown button `{name}`"),
+        };
+        Ok(input | button)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn input_is_held_until_next_entry() {
+        let script = Script::parse(
+            "
+            # frame p1 p2
+            10 RIGHT -
+            40 UP|BUTTON_1 3 # comment
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(script.input(0), (Input::empty(), Input::empty()));
+        assert_eq!(script.input(10), (Input::RIGHT, Input::empty()));
+        assert_eq!(script.input(39), (Input::RIGHT, Input::empty()));
+        assert_eq!(
+            script.input(1000),
+            (Input::UP | Input::BUTTON_1, Input::UP | Input::DOWN)
+        );
+    }
+
+    #[test]
+    fn frames_must_increase() {
+        assert!(Script::parse("10 - -\n10 - -").is_err());
+    }
+
+    #[test]
+    fn start_and_select() {
+        let script = Script::parse("0 START SELECT|LEFT").unwrap();
+        assert_eq!(script.input(0), (Input::START, Input::SELECT | Input::LEFT));
+    }
+
+    #[test]
+    fn unknown_button() {
+        assert!(Script::parse("0 JUMP -").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Checks whether a rom follows the Ultra Zeus spec
+
+use crate::{Error, Rom, STUBBED_MODULES};
+use ultra_crustaceous::Input;
+use wasmi::{core::ValueType, Engine, ExternType, Module};
+
+/// A way in which a rom breaks the spec
+#[derive(thiserror::Error, Debug)]
+pub enum Violation {
+    #[error("missing required export `{0}`")]
+    MissingExport(&'static str),
+    #[error("export `{name}` should be `{expected}`, but is `{actual}`")]
+    WrongExportType {
+        name: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("imports `{module}::{name}`, which hosts don't provide")]
+    UnsupportedImport { module: String, name: String },
+    #[error("first update failed: {0}")]
+    FirstUpdate(Error),
+}
+
+/// What [`check`] found out about a rom
+#[derive(Debug)]
+pub struct Report {
+    pub violations: Vec<Violation>,
+    /// Whether the rom exports `get_palette_buffer_pointer`
+    pub has_palette: bool,
+    /// Whether the rom exports `get_audio_buffer_pointer` and
+    /// `get_audio_sample_count`
+    pub has_audio: bool,
+    /// Whether the rom exports `get_save_buffer_pointer` and
+    /// `get_save_buffer_size`
+    pub has_save_data: bool,
+    /// Whether the rom exports `init`
+    pub has_init: bool,
+    /// Initial size of the exported memory, in 64KiB pages
+    pub initial_pages: Option<u32>,
+    /// Maximum size of the exported memory, in 64KiB pages
+    pub maximum_pages: Option<u32>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Inspects the exports and imports of a rom, and if they look right, runs
+/// its first update to see if the returned buffers fit in its memory
+///
+/// Only fails if the wasm module itself is invalid, spec violations are
+/// collected in the report.
+pub fn check(wasm: &[u8]) -> Result<Report, Error> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm)?;
+
+    let mut report = Report {
+        violations: Vec::new(),
+        has_palette: false,
+        has_audio: false,
+        has_save_data: false,
+        has_init: false,
+        initial_pages: None,
+        maximum_pages: None,
+    };
+
+    for import in module.imports() {
+        let stubbed = matches!(import.ty(), ExternType::Func(_))
+            && STUBBED_MODULES.contains(&import.module());
+        if !stubbed {
+            report.violations.push(Violation::UnsupportedImport {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+            });
+        }
+    }
+
+    let export = |name| {
+        module
+            .exports()
+            .find(|export| export.name() == name)
+            .map(|export| export.ty().clone())
+    };
+
+    match export("memory") {
+        Some(ExternType::Memory(memory)) => {
+            report.initial_pages = Some(memory.initial_pages().into());
+            report.maximum_pages = memory.maximum_pages().map(Into::into);
+        }
+        Some(other) => report.violations.push(Violation::WrongExportType {
+            name: "memory",
+            expected: "memory".to_string(),
+            actual: describe(&other),
+        }),
+        None => report.violations.push(Violation::MissingExport("memory")),
+    }
+
+    let functions: [(&'static str, &[ValueType], &[ValueType], bool); 8] = [
+        ("update", &[ValueType::I32, ValueType::I32], &[], true),
+        ("get_screen_buffer_pointer", &[], &[ValueType::I32], true),
+        ("get_palette_buffer_pointer", &[], &[ValueType::I32], false),
+        ("get_audio_buffer_pointer", &[], &[ValueType::I32], false),
+        ("get_audio_sample_count", &[], &[ValueType::I32], false),
+        ("get_save_buffer_pointer", &[], &[ValueType::I32], false),
+        ("get_save_buffer_size", &[], &[ValueType::I32], false),
+        ("init", &[ValueType::I64], &[], false),
+    ];
+
+    for (name, params, results, required) in functions {
+        let expected = signature(params, results);
+        match export(name) {
+            Some(ExternType::Func(ty)) if ty.params() == params && ty.results() == results => {}
+            Some(other) => report.violations.push(Violation::WrongExportType {
+                name,
+                expected,
+                actual: describe(&other),
+            }),
+            None if required => report.violations.push(Violation::MissingExport(name)),
+            None => {}
+        }
+    }
+
+    report.has_palette = export("get_palette_buffer_pointer").is_some();
+    report.has_init = export("init").is_some();
+
+    // audio and save data are optional, but take both of their exports
+    let mut pair = |first: &'static str, second: &'static str| {
+        match (export(first).is_some(), export(second).is_some()) {
+            (true, true) => return true,
+            (true, false) => report.violations.push(Violation::MissingExport(second)),
+            (false, true) => report.violations.push(Violation::MissingExport(first)),
+            (false, false) => {}
+        }
+        false
+    };
+    report.has_audio = pair("get_audio_buffer_pointer", "get_audio_sample_count");
+    report.has_save_data = pair("get_save_buffer_pointer", "get_save_buffer_size");
+
+    // only worth running if the rom is otherwise sane
+    if report.is_ok() {
+        let first_update =
+            Rom::new(wasm).and_then(|mut rom| rom.step(Input::empty(), Input::empty()).map(|_| ()));
+        if let Err(error) = first_update {
+            report.violations.push(Violation::FirstUpdate(error));
+        }
+    }
+
+    Ok(report)
+}
+
+fn signature(params: &[ValueType], results: &[ValueType]) -> String {
+    let list = |types: &[ValueType]| {
+        types
+            .iter()
+            .map(|ty| format!("{ty:?}").to_lowercase())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!("fn({}) -> ({})", list(params), list(results))
+}
+
+fn describe(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(ty) => signature(ty.params(), ty.results()),
+        ExternType::Global(_) => "global".to_string(),
+        ExternType::Memory(_) => "memory".to_string(),
+        ExternType::Table(_) => "table".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn valid_rom() {
+        let wasm = wat::parse_str(crate::test::INPUT_ROM).unwrap();
+        let report = check(&wasm).unwrap();
+
+        assert!(report.is_ok(), "{:?}", report.violations);
+        assert!(!report.has_palette);
+        assert!(!report.has_audio);
+        assert!(!report.has_save_data);
+        assert!(!report.has_init);
+        assert_eq!(report.initial_pages, Some(2));
+        assert_eq!(report.maximum_pages, None);
+    }
+
+    #[test]
+    fn violations() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (import "env" "abort" (func))
+                (memory (export "memory") 1 1)
+                (func (export "update") (param i32)))
+            "#,
+        )
+        .unwrap();
+        let report = check(&wasm).unwrap();
+
+        assert!(matches!(
+            report.violations[..],
+            [
+                Violation::UnsupportedImport { .. },
+                Violation::WrongExportType { name: "update", .. },
+                Violation::MissingExport("get_screen_buffer_pointer"),
+            ]
+        ));
+        assert_eq!(report.maximum_pages, Some(1));
+    }
+
+    #[test]
+    fn incomplete_audio() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 0)
+                (func (export "get_audio_buffer_pointer") (result i32) i32.const 0)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+        let report = check(&wasm).unwrap();
+
+        assert!(!report.has_audio);
+        assert!(matches!(
+            report.violations[..],
+            [Violation::MissingExport("get_audio_sample_count")]
+        ));
+    }
+
+    #[test]
+    fn save_data() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 0)
+                (func (export "get_save_buffer_pointer") (result i32) i32.const 80000)
+                (func (export "get_save_buffer_size") (result i32) i32.const 8000)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+        let report = check(&wasm).unwrap();
+
+        assert!(report.has_save_data);
+        assert!(matches!(
+            report.violations[..],
+            [Violation::FirstUpdate(Error::SaveTooLarge(8000))]
+        ));
+    }
+
+    #[test]
+    fn buffer_out_of_bounds() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 0)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+        let report = check(&wasm).unwrap();
+
+        assert!(matches!(
+            report.violations[..],
+            [Violation::FirstUpdate(Error::BufferOutOfBounds { .. })]
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::Error;
+use log::error;
+use wasmi::{Caller, Extern, ExternType, Func, Linker, Module, Store, Value};
+
+/// Import modules that hosts provide dummy implementations for
+///
+/// These are the same ones the reference emulator in `index.html` stubs out.
+/// Roms are not supposed to rely on them, but `wasm-bindgen` and the rust
+/// standard library will often link them in anyway.
+pub const STUBBED_MODULES: &[&str] = &["wbg", "wasi_snapshot_preview1"];
+
+/// Defines a do-nothing function for every import of the rom
+pub(crate) fn stub_imports(
+    store: &mut Store<()>,
+    linker: &mut Linker<()>,
+    module: &Module,
+) -> Result<(), Error> {
+    for import in module.imports() {
+        let (module_name, name) = (import.module(), import.name());
+
+        let ty = match import.ty() {
+            ExternType::Func(ty) if STUBBED_MODULES.contains(&module_name) => ty.clone(),
+            _ => {
+                return Err(Error::UnsupportedImport {
+                    module: module_name.to_string(),
+                    name: name.to_string(),
+                })
+            }
+        };
+
+        // the same function may be imported more than once
+        if linker.get(&*store, module_name, name).is_some() {
+            continue;
+        }
+
+        let func = if name == "__wbindgen_throw" {
+            Func::wrap(&mut *store, wbindgen_throw)
+        } else {
+            let results = ty.results().to_vec();
+            Func::new(&mut *store, ty, move |_caller, _params, out| {
+                for (out, ty) in out.iter_mut().zip(&results) {
+                    *out = Value::default(*ty);
+                }
+                Ok(())
+            })
+        };
+
+        linker
+            .define(module_name, name, func)
+            .map_err(wasmi::Error::from)?;
+    }
+
+    Ok(())
+}
+
+/// `wasm-bindgen`'s panic message hook, the rom traps right after calling it
+fn wbindgen_throw(caller: Caller<'_, ()>, ptr: i32, len: i32) {
+    let message = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .and_then(|memory| {
+            let start = ptr as u32 as usize;
+            let bytes = memory
+                .data(&caller)
+                .get(start..start + len as u32 as usize)?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        });
+
+    error!(
+        "rom threw: {}",
+        message.as_deref().unwrap_or("<message out of bounds>")
+    );
+}
@@ -0,0 +1,229 @@
+//! Rewrites roms before instantiating them, so hosts can reach state the rom
+//! keeps to itself
+
+use wasmparser_nostd::{ExternalKind, Operator, Parser, Payload, TypeRef};
+
+/// Prefix of the exports added for mutable globals, followed by the global index
+pub(crate) const GLOBAL_EXPORT_PREFIX: &str = "__ultra_global_";
+
+const EXPORT_SECTION: u8 = 7;
+
+/// The rewritten module and what we learned about it while rewriting it
+pub(crate) struct Instrumented {
+    pub wasm: Vec<u8>,
+    /// Names of the exports added for each mutable global, in index order
+    pub global_exports: Vec<String>,
+    /// Whether the rom has instructions that change its tables
+    pub mutates_tables: bool,
+}
+
+/// Adds an export for every mutable global of the module
+///
+/// Rust roms keep at least the stack pointer in a global that isn't exported.
+pub(crate) fn export_globals(
+    wasm: &[u8],
+) -> Result<Instrumented, wasmparser_nostd::BinaryReaderError> {
+    let mut globals = 0;
+    let mut global_exports = Vec::new();
+    let mut exports = Vec::new();
+    let mut mutates_tables = false;
+    let mut sections = Vec::new();
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+
+        match &payload {
+            Payload::ImportSection(reader) => {
+                for import in reader.clone() {
+                    if let TypeRef::Global(ty) = import?.ty {
+                        if ty.mutable {
+                            global_exports.push(globals);
+                        }
+                        globals += 1;
+                    }
+                }
+            }
+            Payload::GlobalSection(reader) => {
+                for global in reader.clone() {
+                    if global?.ty.mutable {
+                        global_exports.push(globals);
+                    }
+                    globals += 1;
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader.clone() {
+                    let export = export?;
+                    exports.push((export.name.to_string(), export.kind, export.index));
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let mut operators = body.get_operators_reader()?;
+                while !operators.eof() {
+                    mutates_tables |= matches!(
+                        operators.read()?,
+                        Operator::TableSet { .. }
+                            | Operator::TableGrow { .. }
+                            | Operator::TableFill { .. }
+                            | Operator::TableCopy { .. }
+                            | Operator::TableInit { .. }
+                            | Operator::ElemDrop { .. }
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        if let Some((id, range)) = payload.as_section() {
+            sections.push((id, range));
+        }
+    }
+
+    let global_exports: Vec<(String, u32)> = global_exports
+        .into_iter()
+        .map(|index| (format!("{GLOBAL_EXPORT_PREFIX}{index}"), index))
+        .collect();
+
+    let mut export_section = Vec::new();
+    write_leb128(
+        &mut export_section,
+        (exports.len() + global_exports.len()) as u32,
+    );
+    for (name, kind, index) in &exports {
+        write_name(&mut export_section, name);
+        export_section.push(external_kind(*kind));
+        write_leb128(&mut export_section, *index);
+    }
+    for (name, index) in &global_exports {
+        write_name(&mut export_section, name);
+        export_section.push(external_kind(ExternalKind::Global));
+        write_leb128(&mut export_section, *index);
+    }
+
+    let mut out = wasm[..8].to_vec(); // magic and version
+    let mut exports_written = false;
+    for (id, range) in sections {
+        // the export section has to stay in order, even if the rom had none
+        if !exports_written && (id == EXPORT_SECTION || comes_after_exports(id)) {
+            write_section(&mut out, EXPORT_SECTION, &export_section);
+            exports_written = true;
+        }
+        if id != EXPORT_SECTION {
+            write_section(&mut out, id, &wasm[range]);
+        }
+    }
+    if !exports_written {
+        write_section(&mut out, EXPORT_SECTION, &export_section);
+    }
+
+    Ok(Instrumented {
+        wasm: out,
+        global_exports: global_exports.into_iter().map(|(name, _)| name).collect(),
+        mutates_tables,
+    })
+}
+
+/// Whether a section has to come after the export section
+fn comes_after_exports(id: u8) -> bool {
+    // start, element, code, data and data count
+    matches!(id, 8..=12)
+}
+
+fn external_kind(kind: ExternalKind) -> u8 {
+    match kind {
+        ExternalKind::Func => 0,
+        ExternalKind::Table => 1,
+        ExternalKind::Memory => 2,
+        ExternalKind::Global => 3,
+        ExternalKind::Tag => 4,
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_leb128(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_leb128(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exports_mutable_globals() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (global $sp (mut i32) (i32.const 1024))
+                (global $constant i32 (i32.const 1))
+                (global $counter (mut i64) (i64.const 0))
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+
+        let instrumented = export_globals(&wasm).unwrap();
+
+        assert_eq!(
+            instrumented.global_exports,
+            ["__ultra_global_0", "__ultra_global_2"]
+        );
+        assert!(!instrumented.mutates_tables);
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &instrumented.wasm[..]).unwrap();
+        let mut names: Vec<_> = module.exports().map(|export| export.name()).collect();
+        names.sort();
+        assert_eq!(names, ["__ultra_global_0", "__ultra_global_2", "update"]);
+    }
+
+    #[test]
+    fn adds_export_section() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (global (mut i32) (i32.const 0))
+                (func)
+                (start 0))
+            "#,
+        )
+        .unwrap();
+
+        let instrumented = export_globals(&wasm).unwrap();
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &instrumented.wasm[..]).unwrap();
+        assert_eq!(module.exports().count(), 1);
+    }
+
+    #[test]
+    fn detects_table_mutation() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (table 1 funcref)
+                (func (table.set (i32.const 0) (ref.null func))))
+            "#,
+        )
+        .unwrap();
+
+        assert!(export_globals(&wasm).unwrap().mutates_tables);
+    }
+}
@@ -0,0 +1,687 @@
+//! Loads and runs Ultra Zeus roms natively, without a browser
+//!
+//! ```no_run
+//! use ultra_host::Rom;
+//! use ultra_crustaceous::Input;
+//!
+//! let wasm = std::fs::read("dist/ultra_snake/main.wasm").unwrap();
+//! let mut rom = Rom::new(&wasm).unwrap();
+//!
+//! let frame = rom.step(Input::RIGHT, Input::empty()).unwrap();
+//! let top_left = frame.palette[frame.screen.get_pixel(0, 0) as usize];
+//! ```
+
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use thiserror::Error;
+use ultra_crustaceous::{
+    AudioBuffer, Color, Input, PaletteBuffer, ScreenBuffer, DEFAULT_PALETTE, DEFAULT_SEED,
+    MAX_SAVE_SIZE,
+};
+use wasmi::{
+    core::Trap, Engine, Global, Instance, Linker, Memory, Module, Store, TypedFunc, WasmParams,
+    WasmResults,
+};
+use wasmparser_nostd::{Parser, Payload};
+
+pub mod check;
+mod imports;
+mod instrument;
+pub mod rewind;
+mod rle;
+mod state;
+
+pub use imports::STUBBED_MODULES;
+pub use state::SaveState;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid rom: {0}")]
+    Wasm(#[from] wasmi::Error),
+    #[error("invalid rom: {0}")]
+    Parse(#[from] wasmparser_nostd::BinaryReaderError),
+    #[error("rom imports `{module}::{name}`, which hosts don't provide")]
+    UnsupportedImport { module: String, name: String },
+    #[error("rom doesn't export `{0}`")]
+    MissingExport(&'static str),
+    #[error("rom export `{name}` has the wrong type: {source}")]
+    InvalidExport {
+        name: &'static str,
+        source: wasmi::Error,
+    },
+    #[error("rom trapped: {0}")]
+    Trap(#[from] Trap),
+    #[error("{name} at {pointer:#x} doesn't fit in linear memory ({memory_size} bytes)")]
+    BufferOutOfBounds {
+        name: &'static str,
+        pointer: u32,
+        memory_size: usize,
+    },
+    #[error("rom returned {0} audio samples, more than the maximum of {max}", max = AudioBuffer::CAPACITY)]
+    TooManySamples(u32),
+    #[error("rom's save buffer is {0} bytes, more than the maximum of {MAX_SAVE_SIZE}")]
+    SaveTooLarge(u32),
+    #[error("save data is {actual} bytes, but the rom's save buffer is {expected}")]
+    SaveSizeMismatch { expected: u32, actual: usize },
+    #[error("save state was made with a different rom")]
+    StateRomMismatch,
+    #[error("rom changes its tables, which save states don't support")]
+    MutableTables,
+    #[error("invalid save state: {0}")]
+    InvalidState(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Name of the custom section `ultra_meta` stores a rom's metadata in
+const META_SECTION: &str = "ultra.meta";
+
+/// SHA-256 hash of a rom's wasm module, used to identify it
+pub type RomHash = [u8; 32];
+
+/// Hashes the bytes of a rom's wasm module
+///
+/// The `ultra.meta` section is left out, since metadata doesn't change how
+/// the rom runs. Editing a rom's title or controls keeps its movies and save
+/// states working.
+pub fn hash_rom(wasm: &[u8]) -> RomHash {
+    let mut hasher = Sha256::new();
+    let mut hashed = 0;
+    for section in meta_sections(wasm) {
+        hasher.update(&wasm[hashed..section.start]);
+        hashed = section.end;
+    }
+    hasher.update(&wasm[hashed..]);
+    hasher.finalize().into()
+}
+
+/// Byte ranges of the module's `ultra.meta` sections, headers included
+///
+/// Stops at the first thing that doesn't parse, [`Rom::new`] rejects such
+/// modules anyway.
+fn meta_sections(wasm: &[u8]) -> Vec<Range<usize>> {
+    let mut sections = Vec::new();
+    let mut end = 8; // magic and version
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(_) => break,
+        };
+        if let Some((_, range)) = payload.as_section() {
+            if matches!(&payload, Payload::CustomSection(reader) if reader.name() == META_SECTION) {
+                sections.push(end..range.end);
+            }
+            end = range.end;
+        }
+    }
+    sections
+}
+
+/// The output of a rom after an update
+#[derive(Clone, Copy)]
+pub struct Frame<'a> {
+    pub screen: &'a ScreenBuffer,
+    pub palette: &'a PaletteBuffer,
+    /// Audio samples of the frame, empty if the rom has no audio
+    pub audio: &'a [i16],
+}
+
+impl Frame<'_> {
+    /// Converts the frame to 8-bit rgb pixels, 3 bytes per pixel
+    ///
+    /// Rows are ordered top to bottom, which means they are flipped compared
+    /// to the screen buffer, where y points up.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(ScreenBuffer::NUM_PIXELS * 3);
+        for y in (0..ScreenBuffer::HEIGHT).rev() {
+            for x in 0..ScreenBuffer::WIDTH {
+                let index = self.screen.get_pixel(x, y) as usize;
+                // indices outside the palette are undefined, show them as black
+                let rgb = self.palette.get(index).map_or(0, |c| c.to_rgb888());
+                pixels.extend(&rgb.to_be_bytes()[1..]);
+            }
+        }
+        pixels
+    }
+}
+
+/// A loaded and instantiated rom
+pub struct Rom {
+    hash: RomHash,
+    /// Passed to the rom's `init`
+    seed: u64,
+    module: Module,
+    /// Names of the exports added for the rom's mutable globals
+    global_exports: Vec<String>,
+    mutates_tables: bool,
+    instance: RomInstance,
+    screen: Box<ScreenBuffer>,
+    palette: PaletteBuffer,
+    audio: Vec<i16>,
+}
+
+/// The parts of a rom that are recreated when it's instantiated
+struct RomInstance {
+    store: Store<()>,
+    memory: Memory,
+    globals: Vec<Global>,
+    update: TypedFunc<(i32, i32), ()>,
+    get_screen_buffer_pointer: TypedFunc<(), i32>,
+    get_palette_buffer_pointer: Option<TypedFunc<(), i32>>,
+    /// `get_audio_buffer_pointer` and `get_audio_sample_count`
+    get_audio: Option<(TypedFunc<(), i32>, TypedFunc<(), i32>)>,
+    /// Pointer and size of the save buffer, which stay the same for the whole
+    /// session
+    save_buffer: Option<(i32, u32)>,
+}
+
+impl RomInstance {
+    fn new(module: &Module, global_exports: &[String], seed: u64) -> Result<Self, Error> {
+        let engine = module.engine();
+        let mut store = Store::new(engine, ());
+        let mut linker = Linker::new(engine);
+
+        imports::stub_imports(&mut store, &mut linker, module)?;
+
+        let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or(Error::MissingExport("memory"))?;
+
+        let globals = global_exports
+            .iter()
+            .map(|name| {
+                instance
+                    .get_global(&store, name)
+                    .expect("instrumented rom should export its globals")
+            })
+            .collect();
+
+        // before anything else, so the rom can seed its state with it
+        if let Some(init) = typed_func::<i64, ()>(&store, &instance, "init")? {
+            init.call(&mut store, seed as i64)?;
+        }
+
+        let update =
+            typed_func(&store, &instance, "update")?.ok_or(Error::MissingExport("update"))?;
+        let get_screen_buffer_pointer = typed_func(&store, &instance, "get_screen_buffer_pointer")?
+            .ok_or(Error::MissingExport("get_screen_buffer_pointer"))?;
+        let get_palette_buffer_pointer =
+            typed_func(&store, &instance, "get_palette_buffer_pointer")?;
+
+        // audio is optional, but takes both exports
+        let get_audio = match (
+            typed_func(&store, &instance, "get_audio_buffer_pointer")?,
+            typed_func(&store, &instance, "get_audio_sample_count")?,
+        ) {
+            (Some(pointer), Some(count)) => Some((pointer, count)),
+            (None, None) => None,
+            (None, Some(_)) => return Err(Error::MissingExport("get_audio_buffer_pointer")),
+            (Some(_), None) => return Err(Error::MissingExport("get_audio_sample_count")),
+        };
+
+        // so is save data, hosts ask for its buffer before the first update
+        let save_buffer = match (
+            typed_func::<(), i32>(&store, &instance, "get_save_buffer_pointer")?,
+            typed_func::<(), i32>(&store, &instance, "get_save_buffer_size")?,
+        ) {
+            (Some(pointer), Some(size)) => {
+                let pointer = pointer.call(&mut store, ())?;
+                let size = size.call(&mut store, ())? as u32;
+                if size as usize > MAX_SAVE_SIZE {
+                    return Err(Error::SaveTooLarge(size));
+                }
+                let memory = memory.data(&store);
+                buffer_at(memory, "save buffer", pointer, size as usize)?;
+                Some((pointer, size))
+            }
+            (None, None) => None,
+            (None, Some(_)) => return Err(Error::MissingExport("get_save_buffer_pointer")),
+            (Some(_), None) => return Err(Error::MissingExport("get_save_buffer_size")),
+        };
+
+        Ok(Self {
+            store,
+            memory,
+            globals,
+            update,
+            get_screen_buffer_pointer,
+            get_palette_buffer_pointer,
+            get_audio,
+            save_buffer,
+        })
+    }
+}
+
+impl Rom {
+    /// Compiles and instantiates a rom from the bytes of its wasm module
+    ///
+    /// Roms that export `init` get [`DEFAULT_SEED`], which makes every run
+    /// the same.
+    pub fn new(wasm: &[u8]) -> Result<Self, Error> {
+        Self::with_seed(wasm, DEFAULT_SEED)
+    }
+
+    /// Like [`Rom::new`], passing the given seed to the rom's `init`
+    pub fn with_seed(wasm: &[u8], seed: u64) -> Result<Self, Error> {
+        let instrumented = instrument::export_globals(wasm)?;
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &instrumented.wasm[..])?;
+        let instance = RomInstance::new(&module, &instrumented.global_exports, seed)?;
+
+        Ok(Self {
+            hash: hash_rom(wasm),
+            seed,
+            module,
+            global_exports: instrumented.global_exports,
+            mutates_tables: instrumented.mutates_tables,
+            instance,
+            screen: Default::default(),
+            palette: DEFAULT_PALETTE,
+            audio: Vec::new(),
+        })
+    }
+
+    /// Hash of the wasm module the rom was loaded from
+    pub fn hash(&self) -> RomHash {
+        self.hash
+    }
+
+    /// The seed the rom was initialized with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Runs a single frame of the rom with the given player input
+    pub fn step(&mut self, p1: Input, p2: Input) -> Result<Frame<'_>, Error> {
+        let instance = &mut self.instance;
+        instance
+            .update
+            .call(&mut instance.store, (p1.bits() as i32, p2.bits() as i32))?;
+
+        self.read_buffers()?;
+        self.read_audio()?;
+
+        Ok(self.frame())
+    }
+
+    /// The output of the latest call to [`Rom::step`]
+    pub fn frame(&self) -> Frame<'_> {
+        Frame {
+            screen: &self.screen,
+            palette: &self.palette,
+            audio: &self.audio,
+        }
+    }
+
+    /// Whether the rom exports the audio functions
+    pub fn has_audio(&self) -> bool {
+        self.instance.get_audio.is_some()
+    }
+
+    /// Whether the rom exports the save data functions
+    pub fn has_save_data(&self) -> bool {
+        self.instance.save_buffer.is_some()
+    }
+
+    /// The current contents of the rom's save buffer, for hosts to keep
+    /// between sessions
+    pub fn save_data(&self) -> Option<&[u8]> {
+        let instance = &self.instance;
+        let (pointer, size) = instance.save_buffer?;
+        let memory = instance.memory.data(&instance.store);
+        let start = pointer as u32 as usize;
+        // checked when instantiating, and memory never shrinks
+        Some(&memory[start..start + size as usize])
+    }
+
+    /// Restores save data kept from an earlier session
+    ///
+    /// Call before the first [`Rom::step`]. Fails if the rom has no save buffer,
+    /// or if it's a different size than the data, which usually means the data
+    /// is from a different version of the rom.
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let instance = &mut self.instance;
+        let (pointer, size) = instance
+            .save_buffer
+            .ok_or(Error::MissingExport("get_save_buffer_pointer"))?;
+        if data.len() != size as usize {
+            return Err(Error::SaveSizeMismatch {
+                expected: size,
+                actual: data.len(),
+            });
+        }
+        let start = pointer as u32 as usize;
+        instance.memory.data_mut(&mut instance.store)[start..start + data.len()]
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Copies the screen and palette buffers out of the rom's memory
+    fn read_buffers(&mut self) -> Result<(), Error> {
+        let instance = &mut self.instance;
+        let screen_pointer = instance
+            .get_screen_buffer_pointer
+            .call(&mut instance.store, ())?;
+
+        let palette_pointer = match &instance.get_palette_buffer_pointer {
+            Some(func) => Some(func.call(&mut instance.store, ())?),
+            None => None,
+        };
+
+        let memory = instance.memory.data(&instance.store);
+
+        let screen = buffer_at(
+            memory,
+            "screen buffer",
+            screen_pointer,
+            ScreenBuffer::NUM_PIXELS,
+        )?;
+        self.screen.copy_from_slice(screen);
+
+        if let Some(palette_pointer) = palette_pointer {
+            let palette = buffer_at(
+                memory,
+                "palette buffer",
+                palette_pointer,
+                PaletteBuffer::NUM_BYTES,
+            )?;
+            // through Color::from_bytes, so the reserved bits are cleared
+            for (color, bytes) in self.palette.iter_mut().zip(palette.chunks_exact(2)) {
+                *color = Color::from_bytes([bytes[0], bytes[1]]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies the samples of the latest update out of the rom's memory
+    ///
+    /// Separate from the other buffers, since the samples belong to the
+    /// update, and shouldn't be played again when loading a state.
+    fn read_audio(&mut self) -> Result<(), Error> {
+        self.audio.clear();
+
+        let instance = &mut self.instance;
+        let (get_pointer, get_count) = match &instance.get_audio {
+            Some(funcs) => funcs,
+            None => return Ok(()),
+        };
+
+        let pointer = get_pointer.call(&mut instance.store, ())?;
+        let count = get_count.call(&mut instance.store, ())? as u32;
+        if count as usize > AudioBuffer::CAPACITY {
+            return Err(Error::TooManySamples(count));
+        }
+
+        let memory = instance.memory.data(&instance.store);
+        let samples = buffer_at(memory, "audio buffer", pointer, count as usize * 2)?;
+        self.audio.extend(
+            samples
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])),
+        );
+
+        Ok(())
+    }
+}
+
+/// Looks up an exported function, checking that it has the expected signature
+fn typed_func<Params: WasmParams, Results: WasmResults>(
+    store: &Store<()>,
+    instance: &Instance,
+    name: &'static str,
+) -> Result<Option<TypedFunc<Params, Results>>, Error> {
+    instance
+        .get_func(store, name)
+        .map(|func| {
+            func.typed(store)
+                .map_err(|source| Error::InvalidExport { name, source })
+        })
+        .transpose()
+}
+
+/// Looks up a buffer of `len` bytes at a pointer returned by the rom
+fn buffer_at<'a>(
+    memory: &'a [u8],
+    name: &'static str,
+    pointer: i32,
+    len: usize,
+) -> Result<&'a [u8], Error> {
+    let pointer = pointer as u32;
+    let start = pointer as usize;
+    memory
+        .get(start..start + len)
+        .ok_or(Error::BufferOutOfBounds {
+            name,
+            pointer,
+            memory_size: memory.len(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ultra_crustaceous::Color;
+
+    /// Fills the screen with the sum of the inputs
+    pub(crate) const INPUT_ROM: &str = r#"
+        (module
+            (import "wbg" "__wbindgen_throw" (func $throw (param i32 i32)))
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (memory.fill (i32.const 16) (i32.add (local.get 0) (local.get 1)) (i32.const 76800))))
+    "#;
+
+    #[test]
+    fn step() {
+        let wasm = wat::parse_str(INPUT_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+
+        let frame = rom.step(Input::UP, Input::DOWN).unwrap();
+
+        assert_eq!(frame.screen.get_pixel(0, 0), 3);
+        assert_eq!(frame.screen.get_pixel(319, 239), 3);
+        assert_eq!(frame.palette[3].to_bytes(), DEFAULT_PALETTE[3].to_bytes());
+    }
+
+    /// Returns p1 samples counting up from p2
+    const AUDIO_ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (global $count (mut i32) (i32.const 0))
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "get_audio_buffer_pointer") (result i32) i32.const 80000)
+            (func (export "get_audio_sample_count") (result i32) global.get $count)
+            (func (export "update") (param i32 i32) (local $i i32)
+                (global.set $count (local.get 0))
+                (loop $samples
+                    (i32.store16
+                        (i32.add (i32.const 80000) (i32.shl (local.get $i) (i32.const 1)))
+                        (i32.add (local.get 1) (local.get $i)))
+                    (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                    (br_if $samples (i32.lt_u (local.get $i) (local.get 0))))))
+    "#;
+
+    #[test]
+    fn audio() {
+        let mut rom = Rom::new(&wat::parse_str(AUDIO_ROM).unwrap()).unwrap();
+        assert!(rom.has_audio());
+
+        let frame = rom.step(Input::from_bits_truncate(3), Input::from_bits_truncate(10));
+        assert_eq!(frame.unwrap().audio, [10, 11, 12]);
+
+        // hosts without audio don't need anything from roms without it
+        let mut silent = Rom::new(&wat::parse_str(INPUT_ROM).unwrap()).unwrap();
+        assert!(!silent.has_audio());
+        let frame = silent.step(Input::empty(), Input::empty()).unwrap();
+        assert!(frame.audio.is_empty());
+    }
+
+    #[test]
+    fn audio_needs_both_exports() {
+        let wasm = wat::parse_str(AUDIO_ROM.replace("get_audio_sample_count", "nope")).unwrap();
+        assert!(matches!(
+            Rom::new(&wasm),
+            Err(Error::MissingExport("get_audio_sample_count"))
+        ));
+    }
+
+    const SAVE_ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "get_save_buffer_pointer") (result i32) i32.const 80000)
+            (func (export "get_save_buffer_size") (result i32) i32.const 8)
+            (func (export "update") (param i32 i32)
+                (i32.store (i32.const 80000) (i32.add (i32.load (i32.const 80000)) (i32.const 1)))))
+    "#;
+
+    #[test]
+    fn save_data() {
+        let wasm = wat::parse_str(SAVE_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        assert!(rom.has_save_data());
+        assert_eq!(rom.save_data(), Some(&[0; 8][..]));
+
+        rom.load_save_data(&[5, 0, 0, 0, 1, 2, 3, 4]).unwrap();
+        rom.step(Input::empty(), Input::empty()).unwrap();
+        assert_eq!(rom.save_data(), Some(&[6, 0, 0, 0, 1, 2, 3, 4][..]));
+
+        assert!(matches!(
+            rom.load_save_data(&[0; 4]),
+            Err(Error::SaveSizeMismatch {
+                expected: 8,
+                actual: 4
+            })
+        ));
+
+        let silent = Rom::new(&wat::parse_str(INPUT_ROM).unwrap()).unwrap();
+        assert!(!silent.has_save_data());
+        assert_eq!(silent.save_data(), None);
+
+        let too_large = wat::parse_str(SAVE_ROM.replace("i32.const 8)", "i32.const 4097)"));
+        assert!(matches!(
+            Rom::new(&too_large.unwrap()),
+            Err(Error::SaveTooLarge(4097))
+        ));
+    }
+
+    #[test]
+    fn seed() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (func (export "init") (param i64) (i64.store (i32.const 0) (local.get 0)))
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 7)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+
+        // the last byte of the seed ends up in the first pixel
+        let mut rom = Rom::with_seed(&wasm, 0x4200_0000_0000_0000).unwrap();
+        assert_eq!(rom.seed(), 0x4200_0000_0000_0000);
+        let frame = rom.step(Input::empty(), Input::empty()).unwrap();
+        assert_eq!(frame.screen[0], 0x42);
+
+        let mut rom = Rom::new(&wasm).unwrap();
+        let frame = rom.step(Input::empty(), Input::empty()).unwrap();
+        assert_eq!(frame.screen[0], DEFAULT_SEED as u8);
+    }
+
+    #[test]
+    fn reserved_palette_bits() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (data (i32.const 0) "\ff\12\0f\12")
+                (func (export "get_palette_buffer_pointer") (result i32) i32.const 0)
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 64)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let frame = rom.step(Input::empty(), Input::empty()).unwrap();
+
+        assert_eq!(frame.palette.as_bytes()[..4], [0x0f, 0x12, 0x0f, 0x12]);
+        assert_eq!(frame.palette[0], frame.palette[1]);
+    }
+
+    #[test]
+    fn frame_to_rgb8() {
+        let mut screen = ScreenBuffer::default();
+        screen.set_pixel(0, 0, 1);
+        let mut palette = PaletteBuffer::default();
+        palette[1] = Color::from_rgb(0xff8000);
+
+        let pixels = Frame {
+            screen: &screen,
+            palette: &palette,
+            audio: &[],
+        }
+        .to_rgb8();
+
+        // bottom left pixel
+        let i = (ScreenBuffer::HEIGHT - 1) * ScreenBuffer::WIDTH * 3;
+        assert_eq!(pixels[i..i + 3], [0xff, 0x88, 0x00]);
+        assert_eq!(pixels[..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn screen_buffer_out_of_bounds() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 2)
+                (func (export "get_screen_buffer_pointer") (result i32) i32.const 65536)
+                (func (export "update") (param i32 i32)))
+            "#,
+        )
+        .unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+
+        assert!(matches!(
+            rom.step(Input::empty(), Input::empty()),
+            Err(Error::BufferOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn unsupported_import() {
+        let wasm = wat::parse_str(r#"(module (import "env" "abort" (func)))"#).unwrap();
+
+        assert!(matches!(
+            Rom::new(&wasm),
+            Err(Error::UnsupportedImport { .. })
+        ));
+    }
+
+    #[test]
+    fn hash_ignores_metadata() {
+        fn with_section(wasm: &[u8], name: &str) -> Vec<u8> {
+            let mut out = wasm.to_vec();
+            out.push(0);
+            out.push(name.len() as u8 + 4);
+            out.push(name.len() as u8);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b"{}\n");
+            out
+        }
+
+        let wasm = wat::parse_str(INPUT_ROM).unwrap();
+        assert_eq!(hash_rom(&wasm), Sha256::digest(&wasm).as_slice());
+
+        let with_meta = with_section(&wasm, META_SECTION);
+        assert_eq!(hash_rom(&with_meta), hash_rom(&wasm));
+        assert_eq!(Rom::new(&with_meta).unwrap().hash(), hash_rom(&wasm));
+
+        assert_ne!(hash_rom(&with_section(&wasm, "name")), hash_rom(&wasm));
+    }
+}
@@ -0,0 +1,273 @@
+//! Stepping roms backwards in time
+//!
+//! Keeping a full save state for every frame would take far too much memory,
+//! since roms like the ones built with `ultra_bevy` keep their entire world in
+//! linear memory. Instead, only the newest snapshot is kept in full, and each
+//! older one is stored as the run-length encoded XOR of itself and the
+//! snapshot after it. Most of memory stays the same between frames, so the XOR
+//! is mostly zeros and encodes to a few bytes.
+
+use crate::{rle, Error, Rom, SaveState};
+use std::collections::VecDeque;
+
+/// How to get from a snapshot back to the one before it
+struct Delta {
+    globals: Vec<u64>,
+    memory_size: usize,
+    /// Run-length encoded XOR of the two memories, padded to the larger size
+    memory_xor: Vec<u8>,
+}
+
+/// Ring buffer of the most recent snapshots of a rom
+///
+/// Call [`Rewind::capture`] after every [`Rom::step`], and
+/// [`Rewind::step_back`] to go back to the snapshot before.
+///
+/// ```no_run
+/// # use ultra_crustaceous::Input;
+/// # use ultra_host::{rewind::Rewind, Rom};
+/// # fn run(rom: &mut Rom, rewinding: bool) -> Result<(), ultra_host::Error> {
+/// // a minute of history at 60 fps
+/// let mut rewind = Rewind::new(60 * 60);
+///
+/// if rewinding {
+///     rewind.step_back(rom)?;
+/// } else {
+///     rom.step(Input::empty(), Input::empty())?;
+///     rewind.capture(rom)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Rewind {
+    /// Maximum number of snapshots to keep
+    capacity: usize,
+    /// Frames between snapshots
+    interval: usize,
+    /// Frames since the last snapshot
+    skipped: usize,
+    newest: Option<SaveState>,
+    /// Oldest first, the last one leads back from `newest`
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `capacity` snapshots, one for every frame
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: 1,
+            skipped: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Only snapshot every `frames` frames
+    ///
+    /// Uses less memory and time, but each [`Rewind::step_back`] goes back
+    /// that many frames.
+    pub fn with_interval(mut self, frames: usize) -> Self {
+        self.interval = frames.max(1);
+        self
+    }
+
+    /// Snapshots the current state of the rom, if it's time for it
+    pub fn capture(&mut self, rom: &Rom) -> Result<(), Error> {
+        self.skipped += 1;
+        if self.newest.is_some() && self.skipped < self.interval {
+            return Ok(());
+        }
+        self.skipped = 0;
+
+        let state = rom.save_state()?;
+
+        if let Some(previous) = self.newest.take() {
+            if previous.rom_hash != state.rom_hash {
+                self.deltas.clear();
+            } else {
+                self.deltas.push_back(Delta {
+                    memory_xor: rle::encode(&xor(&state.memory, &previous.memory)),
+                    memory_size: previous.memory.len(),
+                    globals: previous.globals,
+                });
+            }
+        }
+        self.newest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Restores the rom to the snapshot before the newest one
+    ///
+    /// Returns `false`, leaving the rom as it is, if there is no older
+    /// snapshot.
+    pub fn step_back(&mut self, rom: &mut Rom) -> Result<bool, Error> {
+        let (newest, delta) = match (&self.newest, self.deltas.back()) {
+            (Some(newest), Some(delta)) => (newest, delta),
+            _ => return Ok(false),
+        };
+
+        // the xor is as long as the longer of the two states
+        let max_len = newest.memory.len().max(delta.memory_size);
+        let memory_xor = rle::decode(&delta.memory_xor, max_len).expect("we encoded it ourselves");
+        let mut memory = xor(&newest.memory, &memory_xor);
+        memory.truncate(delta.memory_size);
+        let older = SaveState {
+            rom_hash: newest.rom_hash,
+            globals: delta.globals.clone(),
+            memory,
+        };
+
+        // keep the newest snapshot if the rom won't take the older one
+        rom.load_state(&older)?;
+        self.deltas.pop_back();
+        self.newest = Some(older);
+        self.skipped = 0;
+        Ok(true)
+    }
+
+    /// Number of snapshots kept
+    pub fn len(&self) -> usize {
+        self.newest.iter().count() + self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Forgets all snapshots
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.skipped = 0;
+    }
+
+    /// Approximate number of bytes used by the snapshots
+    pub fn memory_usage(&self) -> usize {
+        let newest = self
+            .newest
+            .as_ref()
+            .map_or(0, |state| state.memory.len() + state.globals.len() * 8);
+        let deltas: usize = self
+            .deltas
+            .iter()
+            .map(|delta| delta.memory_xor.len() + delta.globals.len() * 8)
+            .sum();
+        newest + deltas
+    }
+}
+
+/// XOR of two byte slices, the shorter one padded with zeros
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = long.to_vec();
+    out.iter_mut().zip(short).for_each(|(byte, other)| *byte ^= other);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::test::COUNTER_ROM;
+    use ultra_crustaceous::Input;
+
+    fn step(rom: &mut Rom) -> u8 {
+        let frame = rom.step(Input::empty(), Input::empty()).unwrap();
+        frame.screen.get_pixel(0, 0)
+    }
+
+    fn pixel(rom: &Rom) -> u8 {
+        rom.frame().screen.get_pixel(0, 0)
+    }
+
+    #[test]
+    fn step_back_every_frame() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let mut rewind = Rewind::new(100);
+
+        for _ in 0..10 {
+            step(&mut rom);
+            rewind.capture(&rom).unwrap();
+        }
+        assert_eq!(rewind.len(), 10);
+
+        // back past the frame that grew memory
+        for expected in (1..10).rev() {
+            assert!(rewind.step_back(&mut rom).unwrap());
+            assert_eq!(pixel(&rom), expected);
+        }
+        assert!(!rewind.step_back(&mut rom).unwrap());
+        assert_eq!(pixel(&rom), 1);
+
+        // and forwards again
+        assert_eq!(step(&mut rom), 2);
+        rewind.capture(&rom).unwrap();
+        assert!(rewind.step_back(&mut rom).unwrap());
+        assert_eq!(pixel(&rom), 1);
+    }
+
+    #[test]
+    fn failed_step_back() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let mut rewind = Rewind::new(100);
+
+        for _ in 0..3 {
+            step(&mut rom);
+            rewind.capture(&rom).unwrap();
+        }
+
+        let mut other = Rom::new(&wat::parse_str(crate::test::INPUT_ROM).unwrap()).unwrap();
+        assert!(matches!(
+            rewind.step_back(&mut other),
+            Err(Error::StateRomMismatch)
+        ));
+
+        // nothing was lost
+        assert_eq!(rewind.len(), 3);
+        assert!(rewind.step_back(&mut rom).unwrap());
+        assert_eq!(pixel(&rom), 2);
+    }
+
+    #[test]
+    fn capacity_and_interval() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let mut rewind = Rewind::new(3).with_interval(2);
+
+        for _ in 0..20 {
+            step(&mut rom);
+            rewind.capture(&rom).unwrap();
+        }
+
+        // snapshots of frames 1, 3, 5 ... 19, only the last 3 kept
+        assert_eq!(rewind.len(), 3);
+        assert!(rewind.step_back(&mut rom).unwrap());
+        assert_eq!(pixel(&rom), 17);
+        assert!(rewind.step_back(&mut rom).unwrap());
+        assert_eq!(pixel(&rom), 15);
+        assert!(!rewind.step_back(&mut rom).unwrap());
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let mut rewind = Rewind::new(300);
+
+        for _ in 0..300 {
+            step(&mut rom);
+            rewind.capture(&rom).unwrap();
+        }
+
+        // one full copy of memory, and a few bytes per frame
+        let memory_size = rom.save_state().unwrap().memory.len();
+        assert!(rewind.memory_usage() < memory_size + 300 * 100);
+    }
+}
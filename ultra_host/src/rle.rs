@@ -0,0 +1,132 @@
+//! Run-length encoding, good at squashing the long runs of zeros in rom memory
+//!
+//! The encoded data is a sequence of runs, each starting with a little endian
+//! base 128 varint `n`. If the lowest bit of `n` is 0, `n >> 1` literal bytes
+//! follow. If it's 1, the next byte is repeated `n >> 1` times.
+
+/// Runs shorter than this are cheaper to store as literals
+const MIN_REPEAT: usize = 4;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let byte = data[i];
+        let run = data[i..].iter().take_while(|b| **b == byte).count();
+
+        if run >= MIN_REPEAT {
+            write_literals(&mut out, &data[literal_start..i]);
+            write_varint(&mut out, (run as u64) << 1 | 1);
+            out.push(byte);
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+
+    write_literals(&mut out, &data[literal_start..]);
+    out
+}
+
+/// Decodes run-length encoded data, `None` if it's malformed or would be
+/// longer than `max_len`
+///
+/// The limit is checked before each run is written, so a few bytes of
+/// corrupt data can't ask for more memory than the caller expects.
+pub fn decode(mut encoded: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+
+    while !encoded.is_empty() {
+        let n = read_varint(&mut encoded)?;
+        let len = usize::try_from(n >> 1).ok()?;
+        if out.len().checked_add(len)? > max_len {
+            return None;
+        }
+
+        if n & 1 == 1 {
+            let (byte, rest) = encoded.split_first()?;
+            out.resize(out.len() + len, *byte);
+            encoded = rest;
+        } else {
+            let literals = encoded.get(..len)?;
+            out.extend_from_slice(literals);
+            encoded = &encoded[len..];
+        }
+    }
+
+    Some(out)
+}
+
+fn write_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    if !literals.is_empty() {
+        write_varint(out, (literals.len() as u64) << 1);
+        out.extend_from_slice(literals);
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut data = vec![1, 2, 3];
+        data.extend([0; 1000]);
+        data.extend([7, 7, 7, 8]);
+
+        let encoded = encode(&data);
+
+        assert_eq!(encoded.len(), 1 + 3 + 2 + 1 + 1 + 4);
+        assert_eq!(decode(&encoded, data.len()).unwrap(), data);
+        assert_eq!(decode(&encode(&[]), 0).unwrap(), []);
+    }
+
+    #[test]
+    fn malformed() {
+        // literal run longer than the data
+        assert_eq!(decode(&[10, 1, 2], 100), None);
+        // repeat without a byte
+        assert_eq!(decode(&[3], 100), None);
+    }
+    #[test]
+    fn too_long() {
+        let data = [5; 100];
+        assert_eq!(decode(&encode(&data), 99), None);
+
+        // a run of 2^62 bytes, from a dozen bytes of data
+        let mut encoded = Vec::new();
+        write_varint(&mut encoded, u64::MAX >> 1 | 1);
+        encoded.push(0);
+        assert_eq!(decode(&encoded, 1 << 20), None);
+        // and literals longer than the limit
+        assert_eq!(decode(&[8, 1, 2, 3, 4], 3), None);
+    }
+}
@@ -0,0 +1,322 @@
+//! Save states, snapshots of a running rom that can be restored later
+//!
+//! ## File format
+//!
+//! All integers are little endian.
+//!
+//! | Bytes | Content                                             |
+//! |-------|-----------------------------------------------------|
+//! | 8     | Magic, `ULTRASAV`                                   |
+//! | 2     | Format version, currently 1                         |
+//! | 32    | Hash of the rom, see [`hash_rom`](crate::hash_rom)  |
+//! | 4     | Number of mutable globals, `g`                      |
+//! | 8 * g | Raw bits of each mutable global, in index order     |
+//! | 4     | Size of linear memory in bytes                      |
+//! | 4     | Size of the encoded memory in bytes, `m`            |
+//! | m     | Linear memory, run-length encoded (see `rle.rs`)    |
+
+use crate::{rle, Error, Rom, RomHash, RomInstance};
+use std::io::{self, Read, Write};
+use wasmi::{
+    core::{Pages, ValueType, F32, F64},
+    Value,
+};
+
+const MAGIC: &[u8; 8] = b"ULTRASAV";
+
+/// Version of the file format written by [`SaveState::write`]
+pub const VERSION: u16 = 1;
+
+/// Size of a wasm memory page
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Everything about a running rom that can change between frames
+///
+/// Tables are not included, since roms only change them if they use the
+/// reference types or bulk memory instructions for tables, which rust roms
+/// don't. Saving such roms fails with [`Error::MutableTables`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaveState {
+    pub rom_hash: RomHash,
+    /// Raw bits of the rom's mutable globals, in index order
+    pub globals: Vec<u64>,
+    /// The rom's entire linear memory
+    pub memory: Vec<u8>,
+}
+
+impl Rom {
+    /// Takes a snapshot of the rom's state
+    pub fn save_state(&self) -> Result<SaveState, Error> {
+        if self.mutates_tables {
+            return Err(Error::MutableTables);
+        }
+
+        let instance = &self.instance;
+
+        let globals = instance
+            .globals
+            .iter()
+            .map(|global| match global.get(&instance.store) {
+                Value::I32(value) => Ok(value as u32 as u64),
+                Value::I64(value) => Ok(value as u64),
+                Value::F32(value) => Ok(value.to_bits() as u64),
+                Value::F64(value) => Ok(value.to_bits()),
+                Value::FuncRef(_) | Value::ExternRef(_) => Err(Error::MutableTables),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SaveState {
+            rom_hash: self.hash,
+            globals,
+            memory: instance.memory.data(&instance.store).to_vec(),
+        })
+    }
+
+    /// Restores a snapshot taken with [`Rom::save_state`]
+    ///
+    /// The snapshot may come from another instance of the same rom.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), Error> {
+        if state.rom_hash != self.hash {
+            return Err(Error::StateRomMismatch);
+        }
+        if state.globals.len() != self.instance.globals.len() {
+            return Err(Error::InvalidState("wrong number of globals"));
+        }
+        if !state.memory.len().is_multiple_of(PAGE_SIZE) {
+            return Err(Error::InvalidState("memory is not a whole number of pages"));
+        }
+
+        let current_size = self.instance.memory.data(&self.instance.store).len();
+
+        // memory can't shrink, so start over with a fresh instance
+        if current_size > state.memory.len() {
+            self.instance = RomInstance::new(&self.module, &self.global_exports, self.seed)?;
+        }
+
+        let instance = &mut self.instance;
+
+        // a fresh instance starts at the rom's initial memory, which can still
+        // be larger than the state
+        let current_size = instance.memory.data(&instance.store).len();
+        if current_size > state.memory.len() {
+            return Err(Error::InvalidState(
+                "memory is smaller than the rom's initial memory",
+            ));
+        }
+
+        let current_pages = current_size / PAGE_SIZE;
+        let missing_pages = Pages::new((state.memory.len() / PAGE_SIZE - current_pages) as u32)
+            .ok_or(Error::InvalidState("memory is too large"))?;
+        instance
+            .memory
+            .grow(&mut instance.store, missing_pages)
+            .map_err(|_| Error::InvalidState("memory is too large"))?;
+
+        instance
+            .memory
+            .data_mut(&mut instance.store)
+            .copy_from_slice(&state.memory);
+
+        for (global, bits) in instance.globals.iter().zip(&state.globals) {
+            let value = match global.ty(&instance.store).content() {
+                ValueType::I32 => Value::I32(*bits as u32 as i32),
+                ValueType::I64 => Value::I64(*bits as i64),
+                ValueType::F32 => Value::F32(F32::from_bits(*bits as u32)),
+                ValueType::F64 => Value::F64(F64::from_bits(*bits)),
+                ValueType::FuncRef | ValueType::ExternRef => return Err(Error::MutableTables),
+            };
+            global
+                .set(&mut instance.store, value)
+                .map_err(|_| Error::InvalidState("global has the wrong type"))?;
+        }
+
+        self.audio.clear();
+        self.read_buffers()
+    }
+}
+
+impl SaveState {
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.rom_hash)?;
+
+        writer.write_all(&(self.globals.len() as u32).to_le_bytes())?;
+        for global in &self.globals {
+            writer.write_all(&global.to_le_bytes())?;
+        }
+
+        let memory = rle::encode(&self.memory);
+        writer.write_all(&(self.memory.len() as u32).to_le_bytes())?;
+        writer.write_all(&(memory.len() as u32).to_le_bytes())?;
+        writer.write_all(&memory)
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidState("not a save state file"));
+        }
+
+        if read_u16(&mut reader)? != VERSION {
+            return Err(Error::InvalidState("unsupported version"));
+        }
+
+        let mut rom_hash = RomHash::default();
+        reader.read_exact(&mut rom_hash)?;
+
+        let global_count = read_u32(&mut reader)?;
+        let globals = (0..global_count)
+            .map(|_| {
+                let mut bits = [0; 8];
+                reader.read_exact(&mut bits)?;
+                Ok(u64::from_le_bytes(bits))
+            })
+            .collect::<io::Result<_>>()?;
+
+        let memory_size = read_u32(&mut reader)? as usize;
+        let encoded_size = read_u32(&mut reader)? as u64;
+
+        let mut encoded = Vec::new();
+        reader.take(encoded_size).read_to_end(&mut encoded)?;
+        if encoded.len() as u64 != encoded_size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let memory = rle::decode(&encoded, memory_size)
+            .filter(|memory| memory.len() == memory_size)
+            .ok_or(Error::InvalidState("corrupt memory"))?;
+
+        Ok(Self {
+            rom_hash,
+            globals,
+            memory,
+        })
+    }
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use ultra_crustaceous::Input;
+
+    /// Counts frames in a global, and grows memory on the third frame
+    pub(crate) const COUNTER_ROM: &str = r#"
+        (module
+            (global $frames (mut i32) (i32.const 0))
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (global.set $frames (i32.add (global.get $frames) (i32.const 1)))
+                (if (i32.eq (global.get $frames) (i32.const 3))
+                    (then (drop (memory.grow (i32.const 1)))))
+                (memory.fill (i32.const 16) (global.get $frames) (i32.const 76800))))
+    "#;
+
+    fn step(rom: &mut Rom) -> u8 {
+        let frame = rom.step(Input::empty(), Input::empty()).unwrap();
+        frame.screen.get_pixel(0, 0)
+    }
+
+    #[test]
+    fn save_and_load() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+
+        step(&mut rom);
+        let state = rom.save_state().unwrap();
+        assert_eq!(state.globals, [1]);
+
+        assert_eq!(step(&mut rom), 2);
+        assert_eq!(step(&mut rom), 3); // grows memory
+
+        // has to shrink memory again
+        rom.load_state(&state).unwrap();
+        assert_eq!(rom.frame().screen.get_pixel(0, 0), 1);
+        assert_eq!(step(&mut rom), 2);
+        assert_eq!(step(&mut rom), 3);
+        assert_eq!(rom.save_state().unwrap().memory.len(), 3 * PAGE_SIZE);
+
+        // load into a fresh instance
+        let mut other = Rom::new(&wasm).unwrap();
+        other.load_state(&rom.save_state().unwrap()).unwrap();
+        assert_eq!(step(&mut other), 4);
+    }
+
+    #[test]
+    fn file_roundtrip() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        step(&mut rom);
+
+        let state = rom.save_state().unwrap();
+        let mut bytes = Vec::new();
+        state.write(&mut bytes).unwrap();
+
+        // mostly zeros, so it compresses well
+        assert!(bytes.len() < 1000);
+        assert_eq!(SaveState::read(&bytes[..]).unwrap(), state);
+    }
+
+    #[test]
+    fn different_rom() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let state = Rom::new(&wasm).unwrap().save_state().unwrap();
+
+        let other = wat::parse_str(crate::test::INPUT_ROM).unwrap();
+        assert!(matches!(
+            Rom::new(&other).unwrap().load_state(&state),
+            Err(Error::StateRomMismatch)
+        ));
+    }
+
+    #[test]
+    fn oversized_memory() {
+        let state = SaveState {
+            rom_hash: RomHash::default(),
+            globals: Vec::new(),
+            memory: vec![0; 10],
+        };
+        let mut bytes = Vec::new();
+        state.write(&mut bytes).unwrap();
+
+        // swap the memory for a run of 2^62 zeros
+        let encoded = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0];
+        bytes.truncate(bytes.len() - rle::encode(&state.memory).len() - 4);
+        bytes.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&encoded);
+
+        assert!(matches!(
+            SaveState::read(&bytes[..]),
+            Err(Error::InvalidState(_))
+        ));
+    }
+
+    #[test]
+    fn smaller_than_initial_memory() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::new(&wasm).unwrap();
+        let mut state = rom.save_state().unwrap();
+        state.memory.truncate(PAGE_SIZE);
+
+        assert!(matches!(
+            rom.load_state(&state),
+            Err(Error::InvalidState(
+                "memory is smaller than the rom's initial memory"
+            ))
+        ));
+    }
+}
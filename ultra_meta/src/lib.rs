@@ -0,0 +1,340 @@
+//! Rom metadata, stored in the `ultra.meta` custom section of the wasm module
+//!
+//! The section holds a UTF-8 JSON object, so browser hosts can read it with
+//! `WebAssembly.Module.customSections` and `JSON.parse`:
+//!
+//! ```json
+//! {
+//!   "title": "Snake",
+//!   "author": "Ultra Zeus",
+//!   "version": "0.1.0",
+//!   "description": "Eat the apples, don't eat yourself",
+//!   "players": 1,
+//!   "controls": { "up": "Turn up", "down": "Turn down" }
+//! }
+//! ```
+//!
+//! All fields are optional. `controls` describes what each of the `Input`
+//! bits does in the game, bits without a description aren't used.
+//!
+//! `cargo xtask dist` fills the section in from `[package.metadata.ultra]` in
+//! the rom crate's `Cargo.toml`, falling back to the package's own name,
+//! version, description and authors.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use ultra_crustaceous::Input;
+
+/// Name of the custom section holding the metadata
+pub const SECTION_NAME: &str = "ultra.meta";
+
+const WASM_HEADER: &[u8] = b"\0asm\x01\0\0\0";
+const CUSTOM_SECTION_ID: u8 = 0;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("not a wasm module")]
+    NotWasm,
+    #[error("wasm module is truncated")]
+    Truncated,
+    #[error("invalid metadata: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("roms are for 1 or 2 players, not {0}")]
+    Players(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub title: String,
+    pub author: String,
+    pub version: String,
+    pub description: String,
+    /// 1 or 2
+    pub players: u8,
+    pub controls: Controls,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            author: String::new(),
+            version: String::new(),
+            description: String::new(),
+            players: 1,
+            controls: Controls::default(),
+        }
+    }
+}
+
+/// What each input does in the game, in words
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Controls {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button_1: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub button_2: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub select: Option<String>,
+}
+
+impl Controls {
+    /// Description of a single input bit
+    pub fn get(&self, input: Input) -> Option<&str> {
+        let description = match input {
+            Input::UP => &self.up,
+            Input::DOWN => &self.down,
+            Input::LEFT => &self.left,
+            Input::RIGHT => &self.right,
+            Input::BUTTON_1 => &self.button_1,
+            Input::BUTTON_2 => &self.button_2,
+            Input::START => &self.start,
+            Input::SELECT => &self.select,
+            _ => return None,
+        };
+        description.as_deref()
+    }
+
+    /// The used inputs and their descriptions, in bit order
+    pub fn iter(&self) -> impl Iterator<Item = (Input, &str)> {
+        [
+            Input::UP,
+            Input::DOWN,
+            Input::LEFT,
+            Input::RIGHT,
+            Input::BUTTON_1,
+            Input::BUTTON_2,
+            Input::START,
+            Input::SELECT,
+        ]
+        .into_iter()
+        .filter_map(|input| Some((input, self.get(input)?)))
+    }
+}
+
+impl Metadata {
+    pub fn from_json(json: &[u8]) -> Result<Self, Error> {
+        let metadata: Self = serde_json::from_slice(json)?;
+        if !(1..=2).contains(&metadata.players) {
+            return Err(Error::Players(metadata.players));
+        }
+        Ok(metadata)
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("metadata is always valid json")
+    }
+
+    /// Reads the metadata of a wasm module, `None` if it has none
+    pub fn read(wasm: &[u8]) -> Result<Option<Self>, Error> {
+        for section in sections(wasm)? {
+            let section = section?;
+            if section.name == Some(SECTION_NAME) {
+                return Self::from_json(section.payload).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns a copy of the wasm module with this metadata, replacing any it
+    /// had before
+    pub fn embed(&self, wasm: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = WASM_HEADER.to_vec();
+        for section in sections(wasm)? {
+            let section = section?;
+            if section.name != Some(SECTION_NAME) {
+                out.extend_from_slice(section.bytes);
+            }
+        }
+
+        let json = self.to_json();
+        let mut content = Vec::new();
+        write_leb128(&mut content, SECTION_NAME.len() as u32);
+        content.extend_from_slice(SECTION_NAME.as_bytes());
+        content.extend_from_slice(&json);
+
+        out.push(CUSTOM_SECTION_ID);
+        write_leb128(&mut out, content.len() as u32);
+        out.extend_from_slice(&content);
+        Ok(out)
+    }
+}
+
+struct Section<'a> {
+    /// The whole section, id and size included
+    bytes: &'a [u8],
+    /// Only set for custom sections
+    name: Option<&'a str>,
+    /// Content after the name of custom sections
+    payload: &'a [u8],
+}
+
+/// The sections of a wasm module, without looking into them
+fn sections(wasm: &[u8]) -> Result<impl Iterator<Item = Result<Section<'_>, Error>>, Error> {
+    let mut rest = wasm.strip_prefix(WASM_HEADER).ok_or(Error::NotWasm)?;
+
+    Ok(std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let section = next_section(rest);
+        rest = match &section {
+            Ok(section) => &rest[section.bytes.len()..],
+            Err(_) => &[],
+        };
+        Some(section)
+    }))
+}
+
+fn next_section(wasm: &[u8]) -> Result<Section<'_>, Error> {
+    let (&id, rest) = wasm.split_first().ok_or(Error::Truncated)?;
+    let (size, rest) = read_leb128(rest)?;
+    let header_size = wasm.len() - rest.len();
+    let content = rest.get(..size as usize).ok_or(Error::Truncated)?;
+    let bytes = &wasm[..header_size + content.len()];
+
+    let (name, payload) = if id == CUSTOM_SECTION_ID {
+        let (name_size, rest) = read_leb128(content)?;
+        let name = rest.get(..name_size as usize).ok_or(Error::Truncated)?;
+        // a name that isn't utf-8 can't be ours
+        (
+            Some(std::str::from_utf8(name).unwrap_or_default()),
+            &rest[name.len()..],
+        )
+    } else {
+        (None, content)
+    };
+
+    Ok(Section {
+        bytes,
+        name,
+        payload,
+    })
+}
+
+/// Reads an unsigned LEB128 number, returns it and the bytes after it
+fn read_leb128(bytes: &[u8]) -> Result<(u32, &[u8]), Error> {
+    let mut value = 0u32;
+    for (i, byte) in bytes.iter().enumerate().take(5) {
+        value |= ((byte & 0x7f) as u32) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok((value, &bytes[i + 1..]));
+        }
+    }
+    Err(Error::Truncated)
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROM: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "update") (param i32 i32))
+            (@custom "other" "keep me")
+        )
+    "#;
+
+    fn snake() -> Metadata {
+        Metadata {
+            title: "Snake".into(),
+            author: "Ultra Zeus".into(),
+            version: "0.1.0".into(),
+            description: "Eat the apples".into(),
+            players: 2,
+            controls: Controls {
+                up: Some("Turn up".into()),
+                button_1: Some("Boost".into()),
+                start: Some("Pause".into()),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn embed_and_read() {
+        let wasm = wat::parse_str(ROM).unwrap();
+        assert_eq!(Metadata::read(&wasm).unwrap(), None);
+
+        let with_meta = snake().embed(&wasm).unwrap();
+        assert_eq!(Metadata::read(&with_meta).unwrap(), Some(snake()));
+
+        // replaces the old metadata, and leaves everything else
+        let mut changed = snake();
+        changed.title = "Snake 2".into();
+        let replaced = changed.embed(&with_meta).unwrap();
+        assert_eq!(Metadata::read(&replaced).unwrap(), Some(changed));
+        assert_eq!(replaced.len(), with_meta.len() + 2);
+        assert!(replaced.windows(7).any(|w| w == b"keep me"));
+
+        wasmi::Module::new(&wasmi::Engine::default(), &replaced[..]).unwrap();
+    }
+
+    #[test]
+    fn defaults() {
+        let metadata = Metadata::from_json(br#"{"title": "Snake"}"#).unwrap();
+        assert_eq!(metadata.title, "Snake");
+        assert_eq!(metadata.players, 1);
+        assert_eq!(metadata.controls, Controls::default());
+
+        assert!(matches!(
+            Metadata::from_json(br#"{"players": 3}"#),
+            Err(Error::Players(3))
+        ));
+    }
+
+    #[test]
+    fn controls() {
+        let controls = snake().controls;
+        assert_eq!(controls.get(Input::UP), Some("Turn up"));
+        assert_eq!(controls.get(Input::DOWN), None);
+        assert_eq!(controls.get(Input::UP | Input::DOWN), None);
+        assert_eq!(
+            controls.iter().collect::<Vec<_>>(),
+            [
+                (Input::UP, "Turn up"),
+                (Input::BUTTON_1, "Boost"),
+                (Input::START, "Pause")
+            ]
+        );
+
+        // unused controls are left out
+        let json = String::from_utf8(snake().to_json()).unwrap();
+        assert!(json.contains("button_1") && !json.contains("button_2"));
+        assert!(json.contains("start") && !json.contains("select"));
+    }
+
+    #[test]
+    fn broken_modules() {
+        assert!(matches!(Metadata::read(b"hello"), Err(Error::NotWasm)));
+
+        let mut wasm = wat::parse_str(ROM).unwrap();
+        wasm.pop();
+        assert!(matches!(Metadata::read(&wasm), Err(Error::Truncated)));
+    }
+}
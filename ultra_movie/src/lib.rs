@@ -0,0 +1,301 @@
+//! Records and replays the input passed to a rom, frame by frame
+//!
+//! Since roms are deterministic, replaying a movie on the same rom reproduces
+//! the exact same frames, which is handy for reproducing bug reports and
+//! making demo loops.
+//!
+//! ## File format
+//!
+//! Movies are stored as binary files, usually with the `.ultramovie`
+//! extension. All integers are little endian.
+//!
+//! | Bytes | Content                                                   |
+//! |-------|-----------------------------------------------------------|
+//! | 8     | Magic, `ULTRAMOV`                                         |
+//! | 2     | Format version, currently 2                               |
+//! | 32    | Hash of the rom, see [`hash_rom`](ultra_host::hash_rom)   |
+//! | 8     | Seed passed to the rom's `init`, not in version 1         |
+//! | 4     | Number of frames, `n`                                     |
+//! | 2 * n | Input bits passed to `update`, `p1` then `p2` every frame |
+//!
+//! ```no_run
+//! use ultra_crustaceous::Input;
+//! use ultra_host::Rom;
+//! use ultra_movie::Movie;
+//!
+//! let wasm = std::fs::read("dist/ultra_snake/main.wasm").unwrap();
+//!
+//! let mut rom = Rom::with_seed(&wasm, 1234).unwrap();
+//! let mut movie = Movie::new(rom.hash(), rom.seed());
+//! for _ in 0..60 {
+//!     rom.step(Input::RIGHT, Input::empty()).unwrap();
+//!     movie.push(Input::RIGHT, Input::empty());
+//! }
+//! movie.write(std::fs::File::create("snake.ultramovie").unwrap()).unwrap();
+//!
+//! // later...
+//! let mut rom = Rom::with_seed(&wasm, movie.seed).unwrap();
+//! movie.replay(&mut rom, |frame_number, frame| {}).unwrap();
+//! ```
+
+use std::io::{self, Read, Write};
+use thiserror::Error;
+use ultra_crustaceous::{Input, DEFAULT_SEED};
+use ultra_host::{Frame, Rom, RomHash};
+
+const MAGIC: &[u8; 8] = b"ULTRAMOV";
+
+/// Version of the file format written by [`Movie::write`]
+pub const VERSION: u16 = 2;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("not an ultra movie file")]
+    InvalidMagic,
+    #[error("unsupported movie version {0}, expected at most {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("movie was recorded with a different rom")]
+    RomMismatch,
+    #[error("movie was recorded with seed {0}, the rom got a different one")]
+    SeedMismatch(u64),
+    #[error(transparent)]
+    Host(#[from] ultra_host::Error),
+}
+
+/// The input of both players for every frame of a play session
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: RomHash,
+    /// Seed the rom was initialized with, see [`Rom::with_seed`]
+    pub seed: u64,
+    pub frames: Vec<(Input, Input)>,
+}
+
+impl Movie {
+    /// Creates an empty movie for the rom with the given hash and seed
+    pub fn new(rom_hash: RomHash, seed: u64) -> Self {
+        Self {
+            rom_hash,
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Appends the input of a frame
+    pub fn push(&mut self, p1: Input, p2: Input) {
+        self.frames.push((p1, p2));
+    }
+
+    /// Number of recorded frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// The input of both players at the given frame, if it was recorded
+    pub fn input(&self, frame: usize) -> Option<(Input, Input)> {
+        self.frames.get(frame).copied()
+    }
+
+    /// Runs every frame of the movie on the rom, calling `on_frame` with the
+    /// output after every update
+    ///
+    /// The rom should be freshly loaded with the movie's seed, or the output
+    /// will differ from the recording.
+    pub fn replay(
+        &self,
+        rom: &mut Rom,
+        mut on_frame: impl FnMut(usize, Frame<'_>),
+    ) -> Result<(), Error> {
+        if rom.hash() != self.rom_hash {
+            return Err(Error::RomMismatch);
+        }
+        if rom.seed() != self.seed {
+            return Err(Error::SeedMismatch(self.seed));
+        }
+
+        for (frame_number, (p1, p2)) in self.frames.iter().enumerate() {
+            let frame = rom.step(*p1, *p2)?;
+            on_frame(frame_number, frame);
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.rom_hash)?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+
+        let inputs: Vec<u8> = self
+            .frames
+            .iter()
+            .flat_map(|(p1, p2)| [p1.bits(), p2.bits()])
+            .collect();
+        writer.write_all(&inputs)
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if !(1..=VERSION).contains(&version) {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut rom_hash = RomHash::default();
+        reader.read_exact(&mut rom_hash)?;
+
+        // version 1 predates `init`
+        let seed = if version >= 2 {
+            let mut seed = [0; 8];
+            reader.read_exact(&mut seed)?;
+            u64::from_le_bytes(seed)
+        } else {
+            DEFAULT_SEED
+        };
+
+        let mut frame_count = [0; 4];
+        reader.read_exact(&mut frame_count)?;
+        let frame_count = u32::from_le_bytes(frame_count) as usize;
+
+        let mut inputs = Vec::new();
+        reader
+            .take(frame_count as u64 * 2)
+            .read_to_end(&mut inputs)?;
+        if inputs.len() != frame_count * 2 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let frames = inputs
+            .chunks_exact(2)
+            .map(|p| {
+                (
+                    Input::from_bits_truncate(p[0]),
+                    Input::from_bits_truncate(p[1]),
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            rom_hash,
+            seed,
+            frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Adds p1 to a counter every frame, and fills the screen with it
+    const COUNTER_ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (i32.store8 (i32.const 0) (i32.add (i32.load8_u (i32.const 0)) (local.get 0)))
+                (memory.fill (i32.const 16) (i32.load8_u (i32.const 0)) (i32.const 76800))))
+    "#;
+
+    fn movie() -> Movie {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut movie = Movie::new(ultra_host::hash_rom(&wasm), 7);
+        movie.push(Input::UP, Input::empty());
+        movie.push(Input::RIGHT | Input::BUTTON_2, Input::DOWN);
+        movie
+    }
+
+    #[test]
+    fn roundtrip() {
+        let movie = movie();
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+
+        assert_eq!(bytes.len(), 8 + 2 + 32 + 8 + 4 + 2 * 2);
+        assert_eq!(Movie::read(&bytes[..]).unwrap(), movie);
+    }
+
+    #[test]
+    fn version_1() {
+        let movie = movie();
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        bytes[8] = 1;
+        bytes.drain(42..50);
+
+        let read = Movie::read(&bytes[..]).unwrap();
+        assert_eq!(read.seed, DEFAULT_SEED);
+        assert_eq!(read.frames, movie.frames);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = Vec::new();
+        movie().write(&mut bytes).unwrap();
+        bytes.pop();
+
+        assert!(matches!(Movie::read(&bytes[..]), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut bytes = Vec::new();
+        movie().write(&mut bytes).unwrap();
+
+        bytes[8] = 3;
+        assert!(matches!(
+            Movie::read(&bytes[..]),
+            Err(Error::UnsupportedVersion(3))
+        ));
+
+        bytes[0] = b'X';
+        assert!(matches!(Movie::read(&bytes[..]), Err(Error::InvalidMagic)));
+    }
+
+    #[test]
+    fn replay() {
+        let wasm = wat::parse_str(COUNTER_ROM).unwrap();
+        let mut rom = Rom::with_seed(&wasm, 7).unwrap();
+
+        let mut pixels = Vec::new();
+        movie()
+            .replay(&mut rom, |_, frame| {
+                pixels.push(frame.screen.get_pixel(0, 0))
+            })
+            .unwrap();
+
+        assert_eq!(pixels, [1, 41]);
+    }
+
+    #[test]
+    fn replay_different_rom() {
+        let wasm = wat::parse_str(COUNTER_ROM.replace(
+            "(memory (export \"memory\") 2)",
+            "(memory (export \"memory\") 3)",
+        ))
+        .unwrap();
+        let mut rom = Rom::with_seed(&wasm, 7).unwrap();
+
+        assert!(matches!(
+            movie().replay(&mut rom, |_, _| {}),
+            Err(Error::RomMismatch)
+        ));
+    }
+}
@@ -0,0 +1,574 @@
+//! Rollback netplay for two player roms
+//!
+//! Each peer runs its own copy of the rom, and sends its player's input to the
+//! other peer over UDP. Instead of waiting for the remote input, frames are
+//! simulated right away with a prediction of it: whatever the remote player
+//! pressed last. When the actual input arrives and turns out to differ from
+//! the prediction, the rom is restored to a save state from before the
+//! mispredicted frame, and simulated forwards again with the right input.
+//!
+//! Roms are deterministic, so both peers end up in the same state, as long as
+//! they're loaded with the same seed, see [`Rom::with_seed`]. To catch
+//! it when that somehow isn't the case, peers exchange hashes of their states
+//! after frames where the input of both players was known, and report a
+//! [`Error::Desync`] if they differ.
+//!
+//! ```no_run
+//! use std::net::UdpSocket;
+//! use ultra_crustaceous::Input;
+//! use ultra_host::Rom;
+//! use ultra_netplay::{Player, Session};
+//!
+//! let wasm = std::fs::read("dist/ultra_bevy_snake/main.wasm").unwrap();
+//! let socket = UdpSocket::bind("0.0.0.0:7000").unwrap();
+//! let peer = "192.168.0.2:7000".parse().unwrap();
+//! let mut session = Session::new(Rom::new(&wasm).unwrap(), socket, peer, Player::One).unwrap();
+//!
+//! // once every 1/60 s
+//! if let Some(frame) = session.advance(Input::RIGHT).unwrap() {
+//!     // draw the frame
+//! }
+//! ```
+
+use log::warn;
+use protocol::Packet;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    net::{SocketAddr, UdpSocket},
+};
+use thiserror::Error;
+use ultra_crustaceous::Input;
+use ultra_host::{Frame, Rom, SaveState};
+
+pub mod protocol;
+
+/// How many frames the rom may run ahead of the remote input by default
+pub const DEFAULT_MAX_PREDICTION: u32 = 8;
+
+/// Number of state hashes kept for comparing with the peer's
+const HASH_HISTORY: usize = 128;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Host(#[from] ultra_host::Error),
+    #[error("the peer is running a different rom")]
+    RomMismatch,
+    #[error("the peer's rom was started with seed {0}, ours with a different one")]
+    SeedMismatch(u64),
+    #[error("state differs from the peer's after frame {0}")]
+    Desync(u32),
+}
+
+/// Which of the rom's players is controlled locally
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+/// A rom synchronized with a peer
+pub struct Session {
+    rom: Rom,
+    socket: UdpSocket,
+    local_player: Player,
+    max_prediction: u32,
+    connected: bool,
+    /// Number of frames simulated
+    frame: u32,
+    local_inputs: Vec<Input>,
+    /// Inputs received from the peer, one for each frame from the start
+    remote_inputs: Vec<Input>,
+    /// The remote input each simulated frame was run with, predicted or not
+    used_remote_inputs: Vec<Input>,
+    /// Number of local inputs the peer has received
+    acked: u32,
+    /// State before each frame that may still be rolled back, in order
+    states: VecDeque<(u32, SaveState)>,
+    /// Hashes of our states after confirmed frames
+    hashes: BTreeMap<u32, u64>,
+    /// Hashes from the peer we don't have our own hash for yet
+    remote_hashes: BTreeMap<u32, u64>,
+}
+
+impl Session {
+    /// Starts a session with the peer at the given address
+    ///
+    /// The peer has to start a session with the other player, and the same
+    /// rom.
+    pub fn new(
+        rom: Rom,
+        socket: UdpSocket,
+        peer: SocketAddr,
+        local_player: Player,
+    ) -> Result<Self, Error> {
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            rom,
+            socket,
+            local_player,
+            max_prediction: DEFAULT_MAX_PREDICTION,
+            connected: false,
+            frame: 0,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            used_remote_inputs: Vec::new(),
+            acked: 0,
+            states: VecDeque::new(),
+            hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+        })
+    }
+
+    /// How many frames the rom may run ahead of the remote input
+    ///
+    /// Higher values hide more latency, but make rollbacks longer.
+    pub fn with_max_prediction(mut self, frames: u32) -> Self {
+        self.max_prediction = frames.max(1);
+        self
+    }
+
+    /// Runs the next frame with the given local input
+    ///
+    /// Returns `None` without running a frame if the rom is too far ahead of
+    /// the remote input, or the peer hasn't been heard from yet. Pass the same
+    /// input again next time.
+    pub fn advance(&mut self, input: Input) -> Result<Option<Frame<'_>>, Error> {
+        self.receive()?;
+
+        if !self.connected || self.frame - self.confirmed_frames() >= self.max_prediction {
+            self.send()?;
+            return Ok(None);
+        }
+
+        let frame = self.frame;
+        self.local_inputs.push(input);
+        self.save_state(frame)?;
+        let remote = self.predict(frame);
+        self.used_remote_inputs.push(remote);
+        self.step(frame, remote)?;
+        self.frame += 1;
+
+        self.send()?;
+        Ok(Some(self.rom.frame()))
+    }
+
+    /// Exchanges input with the peer without running a frame
+    ///
+    /// Useful for waiting for the peer to catch up at the end of a session.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        self.receive()?;
+        self.send()
+    }
+
+    /// The rom's latest frame, possibly based on predicted input
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    /// Number of frames simulated
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Number of frames simulated with the actual input of both players
+    pub fn confirmed_frames(&self) -> u32 {
+        self.frame.min(self.remote_inputs.len() as u32)
+    }
+
+    /// Whether the peer has received all local input
+    pub fn is_acked(&self) -> bool {
+        self.acked == self.frame
+    }
+
+    /// Whether anything has been heard from the peer yet
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn step(&mut self, frame: u32, remote: Input) -> Result<(), Error> {
+        let local = self.local_inputs[frame as usize];
+        let (p1, p2) = match self.local_player {
+            Player::One => (local, remote),
+            Player::Two => (remote, local),
+        };
+        self.rom.step(p1, p2)?;
+        Ok(())
+    }
+
+    /// The actual remote input if we have it, or a guess
+    fn predict(&self, frame: u32) -> Input {
+        let inputs = &self.remote_inputs;
+        inputs
+            .get(frame as usize)
+            .or_else(|| inputs.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Saves the state before the given frame, so it can be rolled back to
+    fn save_state(&mut self, frame: u32) -> Result<(), Error> {
+        let state = self.rom.save_state()?;
+        if frame > 0 && frame as usize <= self.remote_inputs.len() {
+            self.record_hash(frame - 1, state_hash(&state))?;
+        }
+        self.states.push_back((frame, state));
+        Ok(())
+    }
+
+    fn record_hash(&mut self, frame: u32, hash: u64) -> Result<(), Error> {
+        if let Some(remote) = self.remote_hashes.remove(&frame) {
+            if remote != hash {
+                return Err(Error::Desync(frame));
+            }
+        }
+
+        self.hashes.insert(frame, hash);
+        while self.hashes.len() > HASH_HISTORY {
+            self.hashes.pop_first();
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<(), Error> {
+        let received = self.remote_inputs.len();
+        let mut buffer = [0; 2048];
+
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => self.handle_packet(&buffer[..len])?,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // the peer isn't up yet
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if self.remote_inputs.len() > received {
+            self.confirm(received)?;
+        }
+        Ok(())
+    }
+
+    fn handle_packet(&mut self, data: &[u8]) -> Result<(), Error> {
+        let packet = match Packet::decode(data) {
+            Some(packet) => packet,
+            None => {
+                warn!("ignoring malformed packet");
+                return Ok(());
+            }
+        };
+
+        if packet.rom_hash != self.rom.hash() {
+            return Err(Error::RomMismatch);
+        }
+        if packet.seed != self.rom.seed() {
+            return Err(Error::SeedMismatch(packet.seed));
+        }
+
+        self.connected = true;
+        self.acked = self.acked.max(packet.ack.min(self.frame));
+
+        // the peer can't be further ahead than it may predict, so anything
+        // past that is bogus, or sent again once we catch up
+        let received = self.remote_inputs.len();
+        let limit = self.frame.saturating_add(self.max_prediction) as usize;
+        let start = packet.start as usize;
+        if start <= received && received < limit {
+            self.remote_inputs.extend(
+                packet
+                    .inputs
+                    .iter()
+                    .skip(received - start)
+                    .take(limit - received),
+            );
+        }
+
+        if let Some((frame, hash)) = packet.state_hash {
+            match self.hashes.get(&frame) {
+                Some(ours) if *ours != hash => return Err(Error::Desync(frame)),
+                Some(_) => {}
+                None if self
+                    .hashes
+                    .first_key_value()
+                    .is_none_or(|(first, _)| frame > *first) =>
+                {
+                    self.remote_hashes.insert(frame, hash);
+                    while self.remote_hashes.len() > HASH_HISTORY {
+                        self.remote_hashes.pop_first();
+                    }
+                }
+                // too old to compare
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles remote input received after the first `received` frames
+    fn confirm(&mut self, received: usize) -> Result<(), Error> {
+        let confirmed = self.confirmed_frames();
+
+        let mispredicted = (received as u32..confirmed).find(|frame| {
+            self.used_remote_inputs[*frame as usize] != self.remote_inputs[*frame as usize]
+        });
+        if let Some(frame) = mispredicted {
+            self.rollback(frame)?;
+        }
+
+        // states that turned out to be right, the newest one isn't saved yet
+        for frame in received as u32 + 1..=confirmed {
+            if !self.hashes.contains_key(&(frame - 1)) {
+                if let Some(state) = self.state(frame) {
+                    let hash = state_hash(state);
+                    self.record_hash(frame - 1, hash)?;
+                }
+            }
+        }
+
+        while self
+            .states
+            .front()
+            .is_some_and(|(frame, _)| *frame < confirmed)
+        {
+            self.states.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Restores the state before the given frame, and simulates it and the
+    /// frames after it again
+    fn rollback(&mut self, to: u32) -> Result<(), Error> {
+        let index = self
+            .states
+            .iter()
+            .position(|(frame, _)| *frame == to)
+            .expect("states before unconfirmed frames are kept");
+
+        self.rom.load_state(&self.states[index].1)?;
+        self.states.truncate(index);
+
+        for frame in to..self.frame {
+            self.save_state(frame)?;
+            let remote = self.predict(frame);
+            self.used_remote_inputs[frame as usize] = remote;
+            self.step(frame, remote)?;
+        }
+
+        Ok(())
+    }
+
+    fn state(&self, frame: u32) -> Option<&SaveState> {
+        let first = self.states.front()?.0;
+        let (_, state) = self.states.get(frame.checked_sub(first)? as usize)?;
+        Some(state)
+    }
+
+    fn send(&mut self) -> Result<(), Error> {
+        let packet = Packet {
+            rom_hash: self.rom.hash(),
+            seed: self.rom.seed(),
+            ack: self.remote_inputs.len() as u32,
+            start: self.acked,
+            inputs: self.local_inputs[self.acked as usize..].to_vec(),
+            state_hash: self
+                .hashes
+                .last_key_value()
+                .map(|(frame, hash)| (*frame, *hash)),
+        };
+
+        match self.socket.send(&packet.encode()) {
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            result => result.map(|_| ()).map_err(Error::from),
+        }
+    }
+}
+
+/// Hash of everything in a save state, cheap enough to do every frame
+pub fn state_hash(state: &SaveState) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut mix = |word: u64| hash = (hash ^ word).wrapping_mul(PRIME).rotate_left(23);
+
+    state.globals.iter().for_each(|global| mix(*global));
+
+    let words = state.memory.chunks_exact(8);
+    let rest = words.remainder();
+    words.for_each(|word| mix(u64::from_le_bytes(word.try_into().unwrap())));
+    rest.iter().for_each(|byte| mix(*byte as u64));
+
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Keeps a running sum of `p1 * 7 + p2` in a global and draws it
+    const SUM_ROM: &str = r#"
+        (module
+            (global $sum (mut i32) (i32.const 0))
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (global.set $sum
+                    (i32.add
+                        (global.get $sum)
+                        (i32.add (i32.mul (local.get 0) (i32.const 7)) (local.get 1))))
+                (memory.fill (i32.const 16) (global.get $sum) (i32.const 76800))))
+    "#;
+
+    fn sessions() -> (Session, Session) {
+        let wasm = wat::parse_str(SUM_ROM).unwrap();
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        (
+            Session::new(Rom::new(&wasm).unwrap(), a, b_addr, Player::One).unwrap(),
+            Session::new(Rom::new(&wasm).unwrap(), b, a_addr, Player::Two).unwrap(),
+        )
+    }
+
+    fn input(player: u8, frame: u32) -> Input {
+        Input::from_bits_truncate((frame / 3 * (player as u32 + 1)) as u8)
+    }
+
+    /// Polls both sessions until the packets in flight have arrived
+    fn settle(a: &mut Session, b: &mut Session) {
+        for _ in 0..100 {
+            a.poll().unwrap();
+            b.poll().unwrap();
+            let settled = |session: &Session| {
+                session.confirmed_frames() == session.frame() && session.is_acked()
+            };
+            if settled(a) && settled(b) {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn peers_agree() {
+        let (mut a, mut b) = sessions();
+
+        // a runs ahead and has to predict, until it's too far ahead
+        let mut a_frames = 0;
+        for _ in 0..20 {
+            if a.advance(input(0, a.frame())).unwrap().is_some() {
+                a_frames += 1;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            b.poll().unwrap();
+        }
+        assert_eq!(a_frames, DEFAULT_MAX_PREDICTION);
+
+        // then b runs ahead, with a only advancing every few frames
+        for tick in 0.. {
+            if b.frame() < 60 {
+                b.advance(input(1, b.frame())).unwrap();
+            }
+            if a.frame() < 60 && tick % 3 == 0 {
+                a.advance(input(0, a.frame())).unwrap();
+            }
+            if a.frame() == 60 && b.frame() == 60 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        settle(&mut a, &mut b);
+
+        let mut reference = Rom::new(&wat::parse_str(SUM_ROM).unwrap()).unwrap();
+        for frame in 0..60 {
+            reference.step(input(0, frame), input(1, frame)).unwrap();
+        }
+        let expected = state_hash(&reference.save_state().unwrap());
+
+        assert_eq!(state_hash(&a.rom().save_state().unwrap()), expected);
+        assert_eq!(state_hash(&b.rom().save_state().unwrap()), expected);
+        assert!(a.is_acked() && b.is_acked());
+    }
+
+    #[test]
+    fn desync() {
+        let (mut a, mut b) = sessions();
+
+        for _ in 0..10 {
+            a.advance(Input::empty()).unwrap();
+            b.advance(Input::empty()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        settle(&mut a, &mut b);
+
+        // something the peer doesn't know about changes the state
+        let mut state = a.rom.save_state().unwrap();
+        state.globals[0] += 1;
+        a.rom.load_state(&state).unwrap();
+
+        let result = (0..20).try_for_each(|_| {
+            a.advance(Input::empty())?;
+            b.advance(Input::empty())?;
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            Ok(())
+        });
+
+        assert!(matches!(result, Err(Error::Desync(_))));
+    }
+
+    #[test]
+    fn remote_input_window() {
+        let (mut a, _) = sessions();
+        let (rom_hash, seed) = (a.rom.hash(), a.rom.seed());
+        let packet = |start: u32, frames: usize| {
+            Packet {
+                rom_hash,
+                seed,
+                ack: 0,
+                start,
+                inputs: vec![Input::UP; frames],
+                state_hash: None,
+            }
+            .encode()
+        };
+
+        a.handle_packet(&packet(0, 100)).unwrap();
+        assert_eq!(a.remote_inputs.len(), DEFAULT_MAX_PREDICTION as usize);
+        a.handle_packet(&packet(DEFAULT_MAX_PREDICTION, 100))
+            .unwrap();
+        assert_eq!(a.remote_inputs.len(), DEFAULT_MAX_PREDICTION as usize);
+
+        a.advance(Input::empty()).unwrap();
+        a.handle_packet(&packet(0, 100)).unwrap();
+        assert_eq!(a.remote_inputs.len(), DEFAULT_MAX_PREDICTION as usize + 1);
+    }
+
+    #[test]
+    fn seed_mismatch() {
+        let wasm = wat::parse_str(SUM_ROM).unwrap();
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let mut a =
+            Session::new(Rom::with_seed(&wasm, 1).unwrap(), a, b_addr, Player::One).unwrap();
+        let mut b =
+            Session::new(Rom::with_seed(&wasm, 2).unwrap(), b, a_addr, Player::Two).unwrap();
+
+        let result = (0..20).try_for_each(|_| {
+            a.advance(Input::empty())?;
+            b.advance(Input::empty())?;
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            Ok(())
+        });
+
+        assert!(matches!(result, Err(Error::SeedMismatch(_))));
+    }
+}
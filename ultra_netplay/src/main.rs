@@ -0,0 +1,143 @@
+//! Runs a rom without a window, synchronized with a peer over UDP
+//!
+//! Try it with two processes on the same machine:
+//!
+//! ```text
+//! cargo run -p ultra_netplay -- dist/ultra_bevy_snake/main.wasm \
+//!     --player 1 --bind 127.0.0.1:7001 --peer 127.0.0.1:7002 --movie p1.ultramovie
+//! cargo run -p ultra_netplay -- dist/ultra_bevy_snake/main.wasm \
+//!     --player 2 --bind 127.0.0.1:7002 --peer 127.0.0.1:7001 --movie p2.ultramovie
+//! ```
+//!
+//! Both print a hash of the rom's state when they're done, which should be the
+//! same.
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::{
+    fs::{self, File},
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use ultra_crustaceous::DEFAULT_SEED;
+use ultra_host::{hash_rom, Rom};
+use ultra_movie::Movie;
+use ultra_netplay::{state_hash, Player, Session};
+
+#[derive(clap::Parser)]
+struct Opt {
+    #[clap(long = "log", default_value = "Info")]
+    log_level: log::LevelFilter,
+    /// The rom's wasm module
+    rom: PathBuf,
+    /// The player controlled by this process, 1 or 2
+    #[clap(long, possible_values = ["1", "2"])]
+    player: u8,
+    /// Local address to receive packets on
+    #[clap(long)]
+    bind: SocketAddr,
+    /// Address of the other process
+    #[clap(long)]
+    peer: SocketAddr,
+    /// Number of frames to run
+    #[clap(long, default_value = "600")]
+    frames: u32,
+    /// Frames per second
+    #[clap(long, default_value = "60")]
+    fps: u32,
+    /// Movie to take the local player's input from
+    #[clap(long)]
+    movie: Option<PathBuf>,
+    /// Seed passed to the rom's `init`, has to be the same for both peers.
+    /// Movies use the one they were recorded with
+    #[clap(long, conflicts_with = "movie")]
+    seed: Option<u64>,
+    /// Maximum number of frames to run ahead of the peer's input
+    #[clap(long, default_value_t = ultra_netplay::DEFAULT_MAX_PREDICTION)]
+    max_prediction: u32,
+}
+
+fn main() -> Result<()> {
+    let opt: Opt = clap::Parser::parse();
+
+    env_logger::builder()
+        .filter(Some("ultra_netplay"), opt.log_level)
+        .init();
+
+    let wasm =
+        fs::read(&opt.rom).with_context(|| format!("couldn't read rom {}", opt.rom.display()))?;
+
+    let movie = match &opt.movie {
+        Some(path) => {
+            let movie = Movie::read(File::open(path)?)
+                .with_context(|| format!("invalid movie {}", path.display()))?;
+            if movie.rom_hash != hash_rom(&wasm) {
+                bail!("{} was recorded with a different rom", path.display());
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+
+    let seed = match &movie {
+        Some(movie) => movie.seed,
+        None => opt.seed.unwrap_or(DEFAULT_SEED),
+    };
+    let rom = Rom::with_seed(&wasm, seed)?;
+
+    let player = match opt.player {
+        1 => Player::One,
+        _ => Player::Two,
+    };
+
+    let socket = UdpSocket::bind(opt.bind)?;
+    let mut session =
+        Session::new(rom, socket, opt.peer, player)?.with_max_prediction(opt.max_prediction);
+
+    info!("waiting for {}", opt.peer);
+
+    let frame_time = Duration::from_secs(1) / opt.fps.max(1);
+    let mut next_frame = Instant::now();
+
+    while session.frame() < opt.frames {
+        let frame_number = session.frame() as usize;
+        let (p1, p2) = movie
+            .as_ref()
+            .and_then(|movie| movie.input(frame_number))
+            .unwrap_or_default();
+        let input = match player {
+            Player::One => p1,
+            Player::Two => p2,
+        };
+
+        session.advance(input)?;
+
+        next_frame += frame_time;
+        sleep(next_frame.saturating_duration_since(Instant::now()));
+    }
+
+    // wait for the last of the peer's input, and for it to get ours
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while session.confirmed_frames() < session.frame() || !session.is_acked() {
+        if Instant::now() > deadline {
+            bail!("timed out waiting for {}", opt.peer);
+        }
+        session.poll()?;
+        sleep(Duration::from_millis(1));
+    }
+
+    // in case the peer missed our last packets
+    let linger = Instant::now() + Duration::from_millis(200);
+    while Instant::now() < linger {
+        session.poll()?;
+        sleep(Duration::from_millis(1));
+    }
+
+    let hash = state_hash(&session.rom().save_state()?);
+    info!("ran {} frames", session.frame());
+    println!("{hash:016x}");
+
+    Ok(())
+}
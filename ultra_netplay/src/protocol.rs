@@ -0,0 +1,141 @@
+//! Packets sent between the two peers
+//!
+//! Every packet carries all the sender's inputs the receiver hasn't
+//! acknowledged yet, so lost packets are made up for by the next one. All
+//! integers are little endian.
+//!
+//! | Bytes | Content                                                        |
+//! |-------|----------------------------------------------------------------|
+//! | 4     | Magic, `ULNP`                                                  |
+//! | 32    | SHA-256 hash of the rom's wasm module                          |
+//! | 8     | Seed the rom was initialized with                              |
+//! | 4     | Number of the receiver's inputs the sender has received        |
+//! | 4     | Frame of the first input                                       |
+//! | 2     | Number of inputs, `n`                                          |
+//! | n     | Input bits of the sender for consecutive frames                |
+//! | 1     | 1 if a state hash follows, 0 otherwise                         |
+//! | 4     | Frame of the state hash, the last frame it includes            |
+//! | 8     | Hash of the sender's state after that frame                    |
+
+use ultra_crustaceous::Input;
+use ultra_host::RomHash;
+
+const MAGIC: &[u8; 4] = b"ULNP";
+
+/// More inputs than this aren't sent in a single packet
+pub const MAX_INPUTS: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub rom_hash: RomHash,
+    pub seed: u64,
+    pub ack: u32,
+    pub start: u32,
+    pub inputs: Vec<Input>,
+    /// Frame and hash of the sender's most recent confirmed state
+    pub state_hash: Option<(u32, u64)>,
+}
+
+impl Packet {
+    pub fn encode(&self) -> Vec<u8> {
+        let inputs = &self.inputs[..self.inputs.len().min(MAX_INPUTS)];
+
+        let mut out = Vec::with_capacity(4 + 32 + 8 + 4 + 4 + 2 + inputs.len() + 13);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.rom_hash);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.ack.to_le_bytes());
+        out.extend_from_slice(&self.start.to_le_bytes());
+        out.extend_from_slice(&(inputs.len() as u16).to_le_bytes());
+        out.extend(inputs.iter().map(|input| input.bits()));
+
+        match self.state_hash {
+            Some((frame, hash)) => {
+                out.push(1);
+                out.extend_from_slice(&frame.to_le_bytes());
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+
+        out
+    }
+
+    /// Decodes a packet, `None` if it's malformed
+    pub fn decode(mut data: &[u8]) -> Option<Self> {
+        if take(&mut data, 4)? != MAGIC {
+            return None;
+        }
+
+        let rom_hash = take(&mut data, 32)?.try_into().ok()?;
+        let seed = u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+        let ack = read_u32(&mut data)?;
+        let start = read_u32(&mut data)?;
+        let count = u16::from_le_bytes(take(&mut data, 2)?.try_into().ok()?);
+        let inputs = take(&mut data, count as usize)?
+            .iter()
+            .map(|bits| Input::from_bits_truncate(*bits))
+            .collect();
+
+        let state_hash = match take(&mut data, 1)? {
+            [0] => None,
+            [1] => {
+                let frame = read_u32(&mut data)?;
+                let hash = u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+                Some((frame, hash))
+            }
+            _ => return None,
+        };
+
+        data.is_empty().then_some(Self {
+            rom_hash,
+            seed,
+            ack,
+            start,
+            inputs,
+            state_hash,
+        })
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Some(taken)
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(take(data, 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let packet = Packet {
+            rom_hash: [7; 32],
+            seed: 42,
+            ack: 12,
+            start: 10,
+            inputs: vec![Input::UP, Input::empty(), Input::LEFT | Input::BUTTON_1],
+            state_hash: Some((9, 0xdead_beef)),
+        };
+
+        let encoded = packet.encode();
+        assert_eq!(Packet::decode(&encoded), Some(packet.clone()));
+
+        let packet = Packet {
+            state_hash: None,
+            ..packet
+        };
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+
+        assert_eq!(Packet::decode(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(Packet::decode(b"nope"), None);
+    }
+}
@@ -0,0 +1,469 @@
+//! Distributing roms as `.ultra.zip` packages
+//!
+//! A package is a zip archive with a `manifest.toml` at its root, next to the
+//! rom's wasm module and any files the manifest lists:
+//!
+//! ```toml
+//! # version of the package format, currently 1
+//! format = 1
+//! # the rom's wasm module
+//! entry = "main.wasm"
+//! # optional png shown in rom pickers
+//! cover = "cover.png"
+//! # optional movies of the rom, see `ultra_movie`
+//! replays = ["replays/attract.ultramovie"]
+//! # optional files for people, not for the rom, like manuals
+//! assets = ["manual.txt"]
+//!
+//! # the same fields as the rom's ultra.meta section, see `ultra_meta`, and
+//! # the same values if the rom has one
+//! [metadata]
+//! title = "Snake"
+//! players = 1
+//!
+//! [metadata.controls]
+//! up = "Turn up"
+//! ```
+//!
+//! Paths are relative to the root of the archive and use `/`. [`Package`]
+//! checks that all listed files are there and valid before handing out the
+//! rom:
+//!
+//! ```no_run
+//! use ultra_package::Package;
+//!
+//! let mut package = Package::open("dist/ultra_snake.ultra.zip").unwrap();
+//! println!("{}", package.manifest().metadata.title);
+//! let rom = package.rom().unwrap();
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    path::Path,
+};
+use thiserror::Error;
+use ultra_host::{hash_rom, Rom};
+use ultra_meta::Metadata;
+use ultra_movie::Movie;
+use zip::{result::ZipError, ZipArchive};
+
+/// Name of the manifest at the root of the archive
+pub const MANIFEST_NAME: &str = "manifest.toml";
+
+/// Version of the package format described by [`Manifest`]
+pub const FORMAT: u32 = 1;
+
+/// Files in a package can't be larger than this, whatever their zip headers
+/// claim
+pub const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+const WASM_MAGIC: &[u8] = b"\0asm";
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Zip(#[from] ZipError),
+    #[error("invalid manifest: {0}")]
+    Manifest(#[from] toml::de::Error),
+    #[error("unsupported package format {0}, expected {FORMAT}")]
+    UnsupportedFormat(u32),
+    #[error("{0} is listed in the manifest but missing from the package")]
+    MissingFile(String),
+    #[error("{0} is larger than {MAX_FILE_SIZE} bytes")]
+    FileTooLarge(String),
+    #[error("{0} isn't a relative path inside the package")]
+    InvalidPath(String),
+    #[error("entry {0} isn't a wasm module")]
+    InvalidEntry(String),
+    #[error("cover {0} isn't a png")]
+    InvalidCover(String),
+    #[error("replay {path} is invalid: {source}")]
+    InvalidReplay {
+        path: String,
+        source: Box<ultra_movie::Error>,
+    },
+    #[error("replay {0} was recorded with a different rom")]
+    ReplayMismatch(String),
+    #[error("manifest metadata doesn't match the metadata in {0}")]
+    MetadataMismatch(String),
+    #[error(transparent)]
+    Metadata(#[from] ultra_meta::Error),
+    #[error(transparent)]
+    Host(#[from] ultra_host::Error),
+}
+
+/// Contents of `manifest.toml`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub entry: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replays: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assets: Vec<String>,
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+impl Manifest {
+    /// A manifest for just a rom, in `main.wasm`
+    pub fn new(metadata: Metadata) -> Self {
+        Self {
+            format: FORMAT,
+            entry: "main.wasm".into(),
+            cover: None,
+            replays: Vec::new(),
+            assets: Vec::new(),
+            metadata,
+        }
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        let manifest: Self = toml::from_str(toml)?;
+        if manifest.format != FORMAT {
+            return Err(Error::UnsupportedFormat(manifest.format));
+        }
+        // same checks as the ultra.meta section
+        Metadata::from_json(&manifest.metadata.to_json())?;
+        for path in manifest.files() {
+            if !is_valid_path(path) {
+                return Err(Error::InvalidPath(path.into()));
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("manifest is always valid toml")
+    }
+
+    /// Paths of all files listed in the manifest
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(&self.entry)
+            .chain(&self.cover)
+            .chain(&self.replays)
+            .chain(&self.assets)
+            .map(String::as_str)
+    }
+}
+
+/// Relative, without `..` and with `/` separators, like zip entries
+fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.starts_with('/')
+        && !path.contains('\\')
+        && path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// An opened and validated `.ultra.zip` package
+pub struct Package<R> {
+    manifest: Manifest,
+    wasm: Vec<u8>,
+    archive: ZipArchive<R>,
+}
+
+impl Package<File> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read(File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> Package<R> {
+    /// Reads the manifest and checks that the files it lists are valid
+    pub fn read(reader: R) -> Result<Self, Error> {
+        let mut archive = ZipArchive::new(reader)?;
+
+        let manifest = read_file(&mut archive, MANIFEST_NAME)?;
+        let manifest = String::from_utf8(manifest)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let manifest = Manifest::from_toml(&manifest)?;
+
+        for path in manifest.files() {
+            if !archive.file_names().any(|name| name == path) {
+                return Err(Error::MissingFile(path.into()));
+            }
+        }
+
+        let wasm = read_file(&mut archive, &manifest.entry)?;
+        if !wasm.starts_with(WASM_MAGIC) {
+            return Err(Error::InvalidEntry(manifest.entry));
+        }
+        if let Some(metadata) = Metadata::read(&wasm)? {
+            if metadata != manifest.metadata {
+                return Err(Error::MetadataMismatch(manifest.entry));
+            }
+        }
+
+        if let Some(cover) = &manifest.cover {
+            if !read_file(&mut archive, cover)?.starts_with(PNG_MAGIC) {
+                return Err(Error::InvalidCover(cover.clone()));
+            }
+        }
+
+        let rom_hash = hash_rom(&wasm);
+        for path in &manifest.replays {
+            let movie = Movie::read(&read_file(&mut archive, path)?[..]).map_err(|source| {
+                Error::InvalidReplay {
+                    path: path.clone(),
+                    source: Box::new(source),
+                }
+            })?;
+            if movie.rom_hash != rom_hash {
+                return Err(Error::ReplayMismatch(path.clone()));
+            }
+        }
+
+        Ok(Self {
+            manifest,
+            wasm,
+            archive,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The rom's wasm module
+    pub fn wasm(&self) -> &[u8] {
+        &self.wasm
+    }
+
+    /// Loads the rom, ready to run
+    pub fn rom(&self) -> Result<Rom, Error> {
+        Ok(Rom::new(&self.wasm)?)
+    }
+
+    /// The cover image as png, if the package has one
+    pub fn cover(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        match self.manifest.cover.clone() {
+            Some(cover) => Ok(Some(self.file(&cover)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The bundled replays, in the order of the manifest
+    pub fn replays(&mut self) -> Result<Vec<Movie>, Error> {
+        self.manifest
+            .replays
+            .clone()
+            .iter()
+            .map(|path| {
+                let bytes = self.file(path)?;
+                Movie::read(&bytes[..]).map_err(|source| Error::InvalidReplay {
+                    path: path.clone(),
+                    source: Box::new(source),
+                })
+            })
+            .collect()
+    }
+
+    /// Reads any file listed in the manifest
+    pub fn file(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        if !self.manifest.files().any(|listed| listed == path) {
+            return Err(Error::MissingFile(path.into()));
+        }
+        read_file(&mut self.archive, path)
+    }
+}
+
+fn read_file<R: Read + Seek>(archive: &mut ZipArchive<R>, path: &str) -> Result<Vec<u8>, Error> {
+    let file = match archive.by_name(path) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Err(Error::MissingFile(path.into())),
+        Err(e) => return Err(e.into()),
+    };
+    // the size in the header isn't trusted, it's only what the file claims
+    let mut bytes = Vec::new();
+    file.take(MAX_FILE_SIZE + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_FILE_SIZE {
+        return Err(Error::FileTooLarge(path.into()));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use ultra_crustaceous::Input;
+    use zip::{write::FileOptions, ZipWriter};
+
+    const ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) (i32.const 0))
+            (func (export "update") (param i32 i32))
+        )
+    "#;
+
+    fn zip(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    fn manifest() -> Manifest {
+        let mut manifest = Manifest::new(Metadata {
+            title: "Test".into(),
+            ..Default::default()
+        });
+        manifest.cover = Some("cover.png".into());
+        manifest.replays = vec!["replays/one.ultramovie".into()];
+        manifest
+    }
+
+    fn movie(wasm: &[u8]) -> Vec<u8> {
+        let mut movie = Movie::new(hash_rom(wasm), 1);
+        movie.push(Input::UP, Input::empty());
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn open() {
+        let wasm = wat::parse_str(ROM).unwrap();
+        let manifest = manifest();
+        let movie = movie(&wasm);
+        let mut package = Package::read(zip(&[
+            (MANIFEST_NAME, manifest.to_toml().as_bytes()),
+            ("main.wasm", &wasm),
+            ("cover.png", PNG_MAGIC),
+            ("replays/one.ultramovie", &movie),
+        ]))
+        .unwrap();
+
+        assert_eq!(package.manifest(), &manifest);
+        assert_eq!(package.wasm(), wasm);
+        assert_eq!(package.cover().unwrap().unwrap(), PNG_MAGIC);
+        assert_eq!(
+            package.replays().unwrap()[0].frames,
+            [(Input::UP, Input::empty())]
+        );
+        package.rom().unwrap();
+    }
+
+    #[test]
+    fn manifest_toml() {
+        let manifest = Manifest::from_toml(
+            r#"
+            format = 1
+            entry = "game.wasm"
+
+            [metadata]
+            title = "Snake"
+
+            [metadata.controls]
+            up = "Turn up"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.entry, "game.wasm");
+        assert_eq!(manifest.metadata.players, 1);
+        assert_eq!(manifest.metadata.controls.up.as_deref(), Some("Turn up"));
+        assert_eq!(Manifest::from_toml(&manifest.to_toml()).unwrap(), manifest);
+
+        assert!(matches!(
+            Manifest::from_toml("format = 2\nentry = \"main.wasm\""),
+            Err(Error::UnsupportedFormat(2))
+        ));
+        for path in ["", "/main.wasm", "../main.wasm", "a//b.wasm", "a\\b.wasm"] {
+            let toml = format!("format = 1\nentry = {path:?}");
+            assert!(matches!(
+                Manifest::from_toml(&toml),
+                Err(Error::InvalidPath(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_packages() {
+        let wasm = wat::parse_str(ROM).unwrap();
+        let manifest = manifest().to_toml();
+        let manifest = manifest.as_bytes();
+        let movie = movie(&wasm);
+        let other_movie = self::movie(b"\0asm other rom");
+
+        let errors = [
+            Package::read(zip(&[("main.wasm", &wasm)])),
+            Package::read(zip(&[(MANIFEST_NAME, manifest), ("main.wasm", &wasm)])),
+            Package::read(zip(&[
+                (MANIFEST_NAME, manifest),
+                ("main.wasm", b"not wasm"),
+                ("cover.png", PNG_MAGIC),
+                ("replays/one.ultramovie", &movie),
+            ])),
+            Package::read(zip(&[
+                (MANIFEST_NAME, manifest),
+                ("main.wasm", &wasm),
+                ("cover.png", b"GIF89a"),
+                ("replays/one.ultramovie", &movie),
+            ])),
+            Package::read(zip(&[
+                (MANIFEST_NAME, manifest),
+                ("main.wasm", &wasm),
+                ("cover.png", PNG_MAGIC),
+                ("replays/one.ultramovie", &other_movie),
+            ])),
+        ];
+
+        assert!(matches!(&errors[0], Err(Error::MissingFile(path)) if path == MANIFEST_NAME));
+        assert!(matches!(&errors[1], Err(Error::MissingFile(path)) if path == "cover.png"));
+        assert!(matches!(&errors[2], Err(Error::InvalidEntry(_))));
+        assert!(matches!(&errors[3], Err(Error::InvalidCover(_))));
+        assert!(matches!(&errors[4], Err(Error::ReplayMismatch(_))));
+    }
+
+    #[test]
+    fn embedded_metadata() {
+        let manifest = manifest();
+        let read = |metadata: &Metadata| {
+            let wasm = metadata.embed(&wat::parse_str(ROM).unwrap()).unwrap();
+            let movie = movie(&wasm);
+            Package::read(zip(&[
+                (MANIFEST_NAME, manifest.to_toml().as_bytes()),
+                ("main.wasm", &wasm),
+                ("cover.png", PNG_MAGIC),
+                ("replays/one.ultramovie", &movie),
+            ]))
+        };
+
+        read(&manifest.metadata).unwrap();
+
+        let other = Metadata {
+            title: "Other".into(),
+            ..manifest.metadata.clone()
+        };
+        assert!(matches!(read(&other), Err(Error::MetadataMismatch(path)) if path == "main.wasm"));
+    }
+
+    #[test]
+    fn too_large() {
+        let wasm = wat::parse_str(ROM).unwrap();
+        let manifest = manifest().to_toml();
+        let movie = movie(&wasm);
+        let cover = vec![0; MAX_FILE_SIZE as usize + 1];
+        let result = Package::read(zip(&[
+            (MANIFEST_NAME, manifest.as_bytes()),
+            ("main.wasm", &wasm),
+            ("cover.png", &cover),
+            ("replays/one.ultramovie", &movie),
+        ]));
+        assert!(matches!(result, Err(Error::FileTooLarge(path)) if path == "cover.png"));
+    }
+}
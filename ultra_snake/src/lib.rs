@@ -1,56 +1,33 @@
-extern crate wee_alloc;
-
-#[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+// no std and no allocator in the rom, tests build it natively with std
+#![cfg_attr(target_arch = "wasm32", no_std)]
 
 use glam::{ivec2, IVec2};
-use lazy_static::lazy_static;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use std::{collections::VecDeque, iter, sync::RwLock};
-use ultra_crustaceous::{Color, Input, PaletteBuffer, ScreenBuffer};
-use wasm_bindgen::prelude::*;
-
-lazy_static! {
-    static ref GAME: RwLock<SnakeGame> = RwLock::new(SnakeGame::default());
-}
-
-#[wasm_bindgen]
-pub fn get_screen_buffer_pointer() -> *const u8 {
-    let game = GAME.read().expect("couldn't get game read lock");
-    game.output_buffer.as_ptr()
-}
-
-#[wasm_bindgen]
-pub fn get_palette_buffer_pointer() -> *const u8 {
-    let game = GAME.read().expect("couldn't get game read lock");
-    game.palette.as_ptr() as *const u8
-}
+use ultra_crustaceous::{ultra_game, AudioBuffer, Color, Game, Input, PaletteBuffer, ScreenBuffer};
+use ultra_synth::{note, Adsr, Duty, Synth};
 
-#[wasm_bindgen]
-pub fn update(p1: u8, p2: u8) {
-    let p1 = Input::from_bits_truncate(p1);
-    let p2 = Input::from_bits_truncate(p2);
+ultra_game!(SnakeGame, audio);
 
-    GAME.write()
-        .expect("Failed to get game write lock")
-        .update(p1, p2);
+#[cfg(target_arch = "wasm32")]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    core::arch::wasm32::unreachable()
 }
 
-// above is boiler-plate
-
 const TILE_SIZE: usize = 10;
 const MAP_SIZE: IVec2 = IVec2::new(25, 20);
 
 struct SnakeGame {
-    ticks: usize,
     output_buffer: ScreenBuffer,
     palette: PaletteBuffer,
-    snake: VecDeque<IVec2>,
+    audio: AudioBuffer,
+    synth: Synth,
+    snake: Snake,
     food: Option<IVec2>,
     direction: IVec2,
     sleep: u8,
     speed: u8,
-    rng: Option<SmallRng>,
+    rng: SmallRng,
 }
 
 impl Default for SnakeGame {
@@ -63,27 +40,49 @@ impl Default for SnakeGame {
         palette[3] = Color::from_rgb(0x71aa34); // green
         palette[4] = Color::from_rgb(0xa93b3b); // deep red
 
+        let mut synth = Synth::new();
+        synth.pulse1.duty = Duty::Quarter;
+        synth.pulse1.envelope = Adsr::PLUCK;
+
         Self {
             output_buffer: Default::default(),
             palette,
-            snake: VecDeque::from_iter(iter::repeat(start_pos).take(5)),
+            audio: Default::default(),
+            synth,
+            snake: Snake::new(start_pos, 5),
             direction: IVec2::ZERO, // start stationary
             speed: 5,
             sleep: 0,
-            ticks: 0,
             food: None,
-            rng: None,
+            rng: SmallRng::seed_from_u64(ultra_crustaceous::seed()),
         }
     }
 }
 
-impl SnakeGame {
+impl Game for SnakeGame {
     fn update(&mut self, p1: Input, p2: Input) {
-        self.ticks += 1;
+        self.step(p1, p2);
+        self.synth.render(self.audio.frame_mut());
+    }
 
+    fn screen(&self) -> &ScreenBuffer {
+        &self.output_buffer
+    }
+
+    fn palette(&self) -> &PaletteBuffer {
+        &self.palette
+    }
+
+    fn audio(&self) -> Option<&AudioBuffer> {
+        Some(&self.audio)
+    }
+}
+
+impl SnakeGame {
+    fn step(&mut self, p1: Input, p2: Input) {
         let input = p1.union(p2); // let either joystick control
 
-        let input_dir = IVec2::new(input.x() as i32, input.y() as i32);
+        let input_dir = IVec2::new(input.x(), input.y());
 
         if input_dir.x.abs() + input_dir.y.abs() == 1 && input_dir != -self.direction {
             // no diagonal or none movement, also no 180 turns
@@ -97,38 +96,34 @@ impl SnakeGame {
 
         // move snake
         if self.direction != IVec2::ZERO {
-            let head = *self.snake.front().unwrap();
+            let head = self.snake.front();
 
             let new_head_pos = head + self.direction;
             if new_head_pos.x >= 0
                 && new_head_pos.x < MAP_SIZE.x
                 && new_head_pos.y >= 0
                 && new_head_pos.y < MAP_SIZE.y
-                && !self.snake.iter().skip(1).any(|p| p == &new_head_pos)
+                && !self.snake.iter().skip(1).any(|p| p == new_head_pos)
             {
                 self.snake.push_front(new_head_pos);
                 self.snake.pop_back();
                 self.sleep = self.speed;
 
                 if let Some(food) = &self.food {
-                    if self.snake.front().unwrap() == food {
+                    if self.snake.front() == *food {
                         self.food = None;
-                        self.snake.push_back(*self.snake.back().unwrap());
+                        self.snake.push_back(self.snake.back());
+                        // higher pitched the longer the snake
+                        let pitch = 72 + self.snake.len().min(36) as u8;
+                        self.synth.pulse1.note_on(note(pitch));
                     }
                 }
             }
 
-            // currently, we're getting entropy from the number of ticks
-            // passed before we get the first input.
-            // that works ok for snake
-            let rng = self
-                .rng
-                .get_or_insert_with(|| SmallRng::seed_from_u64(self.ticks as u64));
-
             if self.food.is_none() {
                 self.food = Some(ivec2(
-                    rng.gen_range(0..MAP_SIZE.x),
-                    rng.gen_range(0..MAP_SIZE.y),
+                    self.rng.gen_range(0..MAP_SIZE.x),
+                    self.rng.gen_range(0..MAP_SIZE.y),
                 ));
             }
         }
@@ -146,7 +141,7 @@ impl SnakeGame {
         }
 
         // draw snake
-        for tile_pos in self.snake.iter().cloned() {
+        for tile_pos in self.snake.iter() {
             draw_tile(&mut self.output_buffer, tile_pos, 3);
         }
 
@@ -157,7 +152,65 @@ impl SnakeGame {
     }
 }
 
-const SCREEN_SIZE: IVec2 = IVec2::new(SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32);
+/// Tiles of the snake from head to tail, in a ring buffer big enough to fill
+/// the map
+struct Snake {
+    tiles: [IVec2; Self::CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl Snake {
+    const CAPACITY: usize = (MAP_SIZE.x * MAP_SIZE.y) as usize;
+
+    fn new(pos: IVec2, len: usize) -> Self {
+        Self {
+            tiles: [pos; Self::CAPACITY],
+            head: 0,
+            len,
+        }
+    }
+
+    fn get(&self, i: usize) -> IVec2 {
+        self.tiles[(self.head + i) % Self::CAPACITY]
+    }
+
+    fn front(&self) -> IVec2 {
+        self.get(0)
+    }
+
+    fn back(&self) -> IVec2 {
+        self.get(self.len - 1)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> impl Iterator<Item = IVec2> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    fn push_front(&mut self, tile: IVec2) {
+        self.head = (self.head + Self::CAPACITY - 1) % Self::CAPACITY;
+        self.tiles[self.head] = tile;
+        self.len = (self.len + 1).min(Self::CAPACITY);
+    }
+
+    fn pop_back(&mut self) {
+        self.len -= 1;
+    }
+
+    /// Does nothing once the snake covers the whole map
+    fn push_back(&mut self, tile: IVec2) {
+        if self.len < Self::CAPACITY {
+            self.tiles[(self.head + self.len) % Self::CAPACITY] = tile;
+            self.len += 1;
+        }
+    }
+}
+
+const SCREEN_SIZE: IVec2 = IVec2::new(ScreenBuffer::WIDTH as i32, ScreenBuffer::HEIGHT as i32);
 const MAP_POS: IVec2 = IVec2::new(
     SCREEN_SIZE.x / 2 - MAP_SIZE.x * TILE_SIZE as i32 / 2,
     SCREEN_SIZE.y / 2 - MAP_SIZE.y * TILE_SIZE as i32 / 2,
@@ -166,11 +219,11 @@ const MAP_POS: IVec2 = IVec2::new(
 fn draw_tile(buffer: &mut ScreenBuffer, tile: IVec2, color: u8) {
     let start_x = MAP_POS.x as usize + tile.x as usize * TILE_SIZE;
     let start_y = MAP_POS.y as usize + tile.y as usize * TILE_SIZE;
-    let start = start_x + start_y * SCREEN_WIDTH;
+    let start = start_x + start_y * ScreenBuffer::WIDTH;
 
     for y in 0..TILE_SIZE {
         for x in 0..TILE_SIZE {
-            let i = start + x + y * SCREEN_WIDTH;
+            let i = start + x + y * ScreenBuffer::WIDTH;
             if i < buffer.0.len() {
                 buffer[i] = color;
             }
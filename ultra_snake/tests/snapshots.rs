@@ -0,0 +1,11 @@
+use ultra_crustaceous::Input;
+use ultra_snapshot::RomTest;
+
+// the food only shows up once the snake moves
+
+#[test]
+fn idle() {
+    RomTest::built()
+        .hold(10, Input::empty(), Input::empty())
+        .assert_frames([(0, "tests/snapshots/idle.png"), (9, "tests/snapshots/idle.png")]);
+}
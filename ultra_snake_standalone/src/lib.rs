@@ -80,6 +80,8 @@ bitflags! {
         const RIGHT = 1 << 3;
         const BUTTON_1 = 1 << 4;
         const BUTTON_2 = 1 << 5;
+        const START = 1 << 6;
+        const SELECT = 1 << 7;
     }
 }
 
@@ -101,7 +103,9 @@ impl Input {
     }
 }
 
-// the above will probably be the same for 99% of games, move to a crate / macro?
+// the above is the same for most games, `ultra_snake` gets it from the `Game`
+// trait and `ultra_game!` macro in `ultra_crustaceous`. this one shows what
+// they do without any ultra dependencies
 
 const TILE_SIZE: usize = 10;
 const MAP_SIZE: IVec2 = IVec2::new(25, 20);
@@ -0,0 +1,427 @@
+//! Golden frame snapshot tests for roms
+//!
+//! Runs a built rom with some input, and compares selected frames to png
+//! snapshots. Put tests like this in the `tests` folder of a rom crate:
+//!
+//! ```no_run
+//! use ultra_crustaceous::Input;
+//! use ultra_snapshot::RomTest;
+//!
+//! #[test]
+//! fn moves_right() {
+//!     RomTest::built()
+//!         .hold(30, Input::RIGHT, Input::empty())
+//!         .assert_frame(29, "tests/snapshots/moves_right.png");
+//! }
+//! ```
+//!
+//! `RomTest::built` uses the rom in `dist/<package>/main.wasm`, so run
+//! `cargo xtask dist <package>` before the tests, and again after every change
+//! to the rom. If the rom hasn't been built, the snapshot assertions are
+//! skipped with a warning. If it was built before the last change to its crate,
+//! or to a crate it depends on by path, the tests fail, since they'd check an
+//! old rom. Set `ULTRA_SKIP_SNAPSHOTS=1` to skip them anyway.
+//!
+//! Set `ULTRA_BLESS=1` to save the current frames as the new snapshots instead
+//! of comparing them. When a frame doesn't match its snapshot, the actual frame
+//! and an image highlighting the differing pixels are saved next to the
+//! snapshot, as `<name>.actual.png` and `<name>.diff.png`.
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use ultra_crustaceous::{Input, ScreenBuffer, DEFAULT_SEED};
+use ultra_host::Rom;
+use ultra_movie::Movie;
+
+const WIDTH: u32 = ScreenBuffer::WIDTH as u32;
+const HEIGHT: u32 = ScreenBuffer::HEIGHT as u32;
+
+/// A rom and the input to run it with
+pub struct RomTest {
+    /// `None` if snapshots are skipped
+    wasm: Option<Vec<u8>>,
+    inputs: Vec<(Input, Input)>,
+    seed: u64,
+    /// Directory relative snapshot paths are resolved from
+    root: PathBuf,
+    bless: bool,
+}
+
+impl RomTest {
+    /// Tests the given wasm module
+    pub fn new(wasm: Vec<u8>) -> Self {
+        Self {
+            wasm: Some(wasm),
+            inputs: Vec::new(),
+            seed: DEFAULT_SEED,
+            root: manifest_dir(),
+            bless: std::env::var_os("ULTRA_BLESS").is_some_and(|bless| bless != "0"),
+        }
+    }
+
+    /// Tests the rom of the package being tested, built with `cargo xtask dist`
+    ///
+    /// Skips the snapshot assertions with a warning if the rom hasn't been
+    /// built, or `ULTRA_SKIP_SNAPSHOTS` is set. Panics if the rom is older than
+    /// a file of the package or of its path dependencies.
+    pub fn built() -> Self {
+        let package = std::env::var("CARGO_PKG_NAME").expect("not run by cargo");
+        let skip = |reason: &str| {
+            eprintln!("warning: {reason}, skipping snapshots of {package}");
+            Self {
+                wasm: None,
+                ..Self::new(Vec::new())
+            }
+        };
+        if std::env::var_os("ULTRA_SKIP_SNAPSHOTS").is_some_and(|skip| skip != "0") {
+            return skip("ULTRA_SKIP_SNAPSHOTS is set");
+        }
+
+        let path = manifest_dir()
+            .ancestors()
+            .map(|dir| dir.join("dist").join(&package).join("main.wasm"))
+            .find(|path| path.is_file());
+        let Some(path) = path else {
+            return skip(&format!(
+                "{package} hasn't been built, run `cargo xtask dist {package}`"
+            ));
+        };
+
+        let built = modified(&path).expect("couldn't read the rom's modification time");
+        let mut crates = Vec::new();
+        crate_dirs(&manifest_dir(), &mut crates);
+        if crates
+            .iter()
+            .filter_map(|dir| newest_source(dir))
+            .any(|source| source > built)
+        {
+            panic!(
+                "{} is older than the source of {package} or its dependencies, \
+                 run `cargo xtask dist {package}` again",
+                path.display()
+            );
+        }
+
+        let wasm = std::fs::read(&path)
+            .unwrap_or_else(|err| panic!("couldn't read {}: {err}", path.display()));
+        Self::new(wasm)
+    }
+
+    /// Whether to overwrite snapshots instead of comparing against them
+    ///
+    /// Defaults to whether the `ULTRA_BLESS` environment variable is set.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// Directory relative snapshot paths are resolved from
+    ///
+    /// Defaults to the root of the crate being tested.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = root.into();
+        self
+    }
+
+    /// Seed passed to the rom's `init`, defaults to
+    /// [`DEFAULT_SEED`](ultra_crustaceous::DEFAULT_SEED)
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Appends input held for the given number of frames
+    pub fn hold(mut self, frames: usize, p1: Input, p2: Input) -> Self {
+        self.inputs.extend(std::iter::repeat_n((p1, p2), frames));
+        self
+    }
+
+    /// Appends the input of every frame of a movie
+    ///
+    /// The movie's rom hash is ignored, since it changes with every build. Its
+    /// seed replaces the current one.
+    pub fn movie(mut self, path: impl AsRef<Path>) -> Self {
+        let path = self.root.join(path);
+        let file = File::open(&path)
+            .unwrap_or_else(|err| panic!("couldn't open {}: {err}", path.display()));
+        let movie = Movie::read(file)
+            .unwrap_or_else(|err| panic!("couldn't read {}: {err}", path.display()));
+        self.seed = movie.seed;
+        self.inputs.extend(movie.frames);
+        self
+    }
+
+    /// Checks that the frame with the given number matches a snapshot
+    ///
+    /// Frames are counted from 0, after the input runs out, no buttons are
+    /// pressed.
+    pub fn assert_frame(&self, frame: usize, snapshot: impl AsRef<Path>) {
+        self.assert_frames([(frame, snapshot)]);
+    }
+
+    /// Checks several frames of the same run against their snapshots
+    pub fn assert_frames<P: AsRef<Path>>(&self, snapshots: impl IntoIterator<Item = (usize, P)>) {
+        let wasm = match &self.wasm {
+            Some(wasm) => wasm,
+            None => return,
+        };
+
+        let mut snapshots: Vec<(usize, PathBuf)> = snapshots
+            .into_iter()
+            .map(|(frame, path)| (frame, self.root.join(path)))
+            .collect();
+        snapshots.sort_by_key(|(frame, _)| *frame);
+
+        let last_frame = match snapshots.last() {
+            Some((frame, _)) => *frame,
+            None => return,
+        };
+
+        let mut rom = Rom::with_seed(wasm, self.seed).expect("couldn't load rom");
+        let mut failures = Vec::new();
+
+        for frame_number in 0..=last_frame {
+            let (p1, p2) = self.inputs.get(frame_number).copied().unwrap_or_default();
+            let frame = rom
+                .step(p1, p2)
+                .unwrap_or_else(|err| panic!("frame {frame_number} failed: {err}"));
+
+            for (_, path) in snapshots.iter().filter(|(f, _)| *f == frame_number) {
+                let pixels = frame.to_rgb8();
+                if let Err(failure) = self.compare(&pixels, path) {
+                    failures.push(format!("frame {frame_number}: {failure}"));
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            panic!(
+                "{}\nrun with ULTRA_BLESS=1 to update the snapshots",
+                failures.join("\n")
+            );
+        }
+    }
+
+    fn compare(&self, pixels: &[u8], path: &Path) -> Result<(), String> {
+        if self.bless {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            return write_png(path, pixels);
+        }
+
+        let expected = read_png(path)?;
+
+        let different = pixels
+            .chunks_exact(3)
+            .zip(expected.chunks_exact(3))
+            .filter(|(a, b)| a != b)
+            .count();
+
+        if different == 0 {
+            return Ok(());
+        }
+
+        let actual_path = path.with_extension("actual.png");
+        let diff_path = path.with_extension("diff.png");
+        write_png(&actual_path, pixels)?;
+        write_png(&diff_path, &diff(pixels, &expected))?;
+
+        Err(format!(
+            "{different} pixels differ from {}, see {} and {}",
+            path.display(),
+            actual_path.display(),
+            diff_path.display()
+        ))
+    }
+}
+
+/// Differing pixels in red, the rest of the expected image dimmed
+fn diff(actual: &[u8], expected: &[u8]) -> Vec<u8> {
+    actual
+        .chunks_exact(3)
+        .zip(expected.chunks_exact(3))
+        .flat_map(|(a, b)| {
+            if a == b {
+                let gray = ((b[0] as u16 + b[1] as u16 + b[2] as u16) / 3 / 4) as u8;
+                [gray; 3]
+            } else {
+                [0xff, 0, 0]
+            }
+        })
+        .collect()
+}
+
+fn manifest_dir() -> PathBuf {
+    std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Adds the directory of a crate and of its path dependencies, recursively
+///
+/// Optional dependencies are included even when they aren't enabled, which at
+/// worst asks for a rebuild that wasn't needed.
+fn crate_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) {
+    let Ok(dir) = dir.canonicalize() else {
+        return;
+    };
+    if dirs.contains(&dir) {
+        return;
+    }
+    dirs.push(dir.clone());
+
+    let manifest = std::fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| manifest.parse::<toml::Value>().ok());
+    let Some(manifest) = manifest else {
+        return;
+    };
+
+    // dev-dependencies aren't part of the rom
+    let targets = manifest
+        .get("target")
+        .and_then(|targets| targets.as_table());
+    let tables = targets
+        .into_iter()
+        .flat_map(|targets| targets.values())
+        .chain([&manifest])
+        .flat_map(|table| ["dependencies", "build-dependencies"].map(|key| table.get(key)))
+        .flatten()
+        .filter_map(|deps| deps.as_table());
+
+    for deps in tables {
+        for dep in deps.values() {
+            if let Some(path) = dep.get("path").and_then(|path| path.as_str()) {
+                crate_dirs(&dir.join(path), dirs);
+            }
+        }
+    }
+}
+
+/// When the manifest or a file in `src` of a crate last changed
+fn newest_source(crate_dir: &Path) -> Option<SystemTime> {
+    fn newest_in(dir: &Path) -> Option<SystemTime> {
+        std::fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.is_dir() {
+                    newest_in(&path)
+                } else {
+                    modified(&path)
+                }
+            })
+            .max()
+    }
+
+    modified(&crate_dir.join("Cargo.toml")).max(newest_in(&crate_dir.join("src")))
+}
+
+fn write_png(path: &Path, pixels: &[u8]) -> Result<(), String> {
+    let write = || -> Result<(), Box<dyn std::error::Error>> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(pixels)?;
+        Ok(())
+    };
+    write().map_err(|err| format!("couldn't write {}: {err}", path.display()))
+}
+
+fn read_png(path: &Path) -> Result<Vec<u8>, String> {
+    let read = || -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+
+        if (info.width, info.height, info.color_type) != (WIDTH, HEIGHT, png::ColorType::Rgb) {
+            return Err(format!(
+                "expected a {WIDTH}x{HEIGHT} rgb image, got a {}x{} {:?} image",
+                info.width, info.height, info.color_type
+            )
+            .into());
+        }
+
+        pixels.truncate(info.buffer_size());
+        Ok(pixels)
+    };
+    read().map_err(|err| format!("couldn't read snapshot {}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Fills the screen with p1
+    const INPUT_ROM: &str = r#"
+        (module
+            (memory (export "memory") 2)
+            (func (export "get_screen_buffer_pointer") (result i32) i32.const 16)
+            (func (export "update") (param i32 i32)
+                (memory.fill (i32.const 16) (local.get 0) (i32.const 76800))))
+    "#;
+
+    fn empty_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ultra_snapshot_{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn rom_test(root: &Path) -> RomTest {
+        RomTest::new(wat::parse_str(INPUT_ROM).unwrap())
+            .root(root)
+            .hold(2, Input::UP, Input::empty())
+            .hold(1, Input::DOWN, Input::empty())
+    }
+
+    #[test]
+    fn bless_then_compare() {
+        let root = empty_dir("bless");
+
+        rom_test(&root)
+            .bless(true)
+            .assert_frames([(0, "up.png"), (2, "down.png")]);
+
+        rom_test(&root)
+            .bless(false)
+            .assert_frames([(1, "up.png"), (2, "down.png")]);
+    }
+
+    #[test]
+    fn mismatch() {
+        let root = empty_dir("mismatch");
+        rom_test(&root).bless(true).assert_frame(0, "up.png");
+
+        let test = rom_test(&root).bless(false);
+        let result = std::panic::catch_unwind(|| test.assert_frame(2, "up.png"));
+
+        assert!(result.is_err());
+        assert!(test.root.join("up.actual.png").exists());
+        assert_eq!(
+            read_png(&test.root.join("up.diff.png")).unwrap()[..3],
+            [0xff, 0, 0]
+        );
+    }
+
+    #[test]
+    fn missing_snapshot() {
+        let test = rom_test(&empty_dir("missing")).bless(false);
+        let result = std::panic::catch_unwind(|| test.assert_frame(0, "nope.png"));
+
+        assert!(result.is_err());
+    }
+}
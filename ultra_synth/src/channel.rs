@@ -0,0 +1,288 @@
+use crate::{
+    envelope::{Adsr, Envelope, MAX_LEVEL},
+    SAMPLE_RATE,
+};
+
+/// Peak amplitude of a single channel at full volume, low enough that all
+/// channels can play at once without clipping
+pub(crate) const AMPLITUDE: i32 = 6000;
+
+/// Phase, envelope and volume, the parts all the tone channels share
+#[derive(Clone, Copy, Debug)]
+struct Voice {
+    /// Position in the waveform, a full period is the whole range of `u32`
+    phase: u32,
+    /// Change in phase per sample
+    step: u32,
+    envelope: Envelope,
+}
+
+impl Voice {
+    const fn new() -> Self {
+        Self {
+            phase: 0,
+            step: 0,
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn set_frequency(&mut self, frequency: f32) {
+        self.step = (frequency * (4_294_967_296.0 / SAMPLE_RATE as f32)) as u32;
+    }
+
+    /// Advances a sample, returns the phase before it, and whether it
+    /// wrapped around
+    fn advance(&mut self) -> (u32, bool) {
+        let phase = self.phase;
+        let (next, wrapped) = phase.overflowing_add(self.step);
+        self.phase = next;
+        (phase, wrapped)
+    }
+
+    /// Scales a waveform sample, from `-AMPLITUDE` to `AMPLITUDE`, by the
+    /// envelope and volume
+    fn amplify(&mut self, adsr: &Adsr, volume: u8, sample: i32) -> i32 {
+        let level = self.envelope.next(adsr) as i32;
+        sample * level / MAX_LEVEL as i32 * volume as i32 / 255
+    }
+}
+
+macro_rules! tone_methods {
+    () => {
+        /// Starts a note at the given frequency in Hz, see [`crate::note`]
+        pub fn note_on(&mut self, frequency: f32) {
+            self.voice.set_frequency(frequency);
+            self.voice.envelope.start(&self.envelope);
+        }
+
+        /// Releases the current note
+        pub fn note_off(&mut self) {
+            self.voice.envelope.release(&self.envelope);
+        }
+
+        /// Changes the frequency of the current note without restarting it,
+        /// for slides and vibrato
+        pub fn set_frequency(&mut self, frequency: f32) {
+            self.voice.set_frequency(frequency);
+        }
+
+        /// Whether the channel is making any sound
+        pub fn is_playing(&self) -> bool {
+            self.voice.envelope.is_active()
+        }
+    };
+}
+
+/// Fraction of a pulse wave's period that it's high
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Duty {
+    /// 12.5%, thin and nasal
+    Eighth,
+    /// 25%
+    Quarter,
+    /// 50%, a square wave
+    #[default]
+    Half,
+    /// 75%, sounds the same as 25%
+    ThreeQuarters,
+}
+
+/// A pulse wave channel
+#[derive(Clone, Copy, Debug)]
+pub struct Pulse {
+    pub duty: Duty,
+    pub envelope: Adsr,
+    /// From 0 to 255
+    pub volume: u8,
+    voice: Voice,
+}
+
+impl Pulse {
+    pub const fn new() -> Self {
+        Self {
+            duty: Duty::Half,
+            envelope: Adsr::HOLD,
+            volume: 255,
+            voice: Voice::new(),
+        }
+    }
+
+    tone_methods!();
+
+    pub(crate) fn next(&mut self) -> i32 {
+        let (phase, _) = self.voice.advance();
+        let high = match self.duty {
+            Duty::Eighth => phase < u32::MAX / 8,
+            Duty::Quarter => phase < u32::MAX / 4,
+            Duty::Half => phase < u32::MAX / 2,
+            Duty::ThreeQuarters => phase < u32::MAX / 4 * 3,
+        };
+        let sample = if high { AMPLITUDE } else { -AMPLITUDE };
+        self.voice.amplify(&self.envelope, self.volume, sample)
+    }
+}
+
+impl Default for Pulse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A triangle wave channel, soft and good for bass lines
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub envelope: Adsr,
+    /// From 0 to 255
+    pub volume: u8,
+    voice: Voice,
+}
+
+impl Triangle {
+    pub const fn new() -> Self {
+        Self {
+            envelope: Adsr::HOLD,
+            volume: 255,
+            voice: Voice::new(),
+        }
+    }
+
+    tone_methods!();
+
+    pub(crate) fn next(&mut self) -> i32 {
+        let (phase, _) = self.voice.advance();
+        // rises over the first half of the period, falls over the second
+        let position = (phase >> 16) as i32;
+        let rising = if position < 0x8000 {
+            position
+        } else {
+            0xffff - position
+        };
+        let sample = (rising * 2 - 0x8000) * AMPLITUDE / 0x8000;
+        self.voice.amplify(&self.envelope, self.volume, sample)
+    }
+}
+
+impl Default for Triangle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A noise channel, for drums and explosions
+///
+/// The noise comes from a 15-bit linear feedback shift register, clocked at
+/// the note's frequency. Higher frequencies give brighter noise.
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+    pub envelope: Adsr,
+    /// From 0 to 255
+    pub volume: u8,
+    /// Repeats after 93 steps instead of 32767, for a metallic buzz
+    pub short: bool,
+    voice: Voice,
+    shift_register: u16,
+}
+
+impl Noise {
+    pub const fn new() -> Self {
+        Self {
+            envelope: Adsr::HOLD,
+            volume: 255,
+            short: false,
+            voice: Voice::new(),
+            shift_register: 1,
+        }
+    }
+
+    tone_methods!();
+
+    pub(crate) fn next(&mut self) -> i32 {
+        let (_, clocked) = self.voice.advance();
+        if clocked {
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        }
+        let sample = if self.shift_register & 1 == 0 {
+            AMPLITUDE
+        } else {
+            -AMPLITUDE
+        };
+        self.voice.amplify(&self.envelope, self.volume, sample)
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Plays back recorded 8-bit samples
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    /// From 0 to 255
+    pub volume: u8,
+    samples: &'static [i8],
+    looping: bool,
+    /// Position in the samples, 32.32 fixed point
+    position: u64,
+    /// Change in position per output sample, 32.32 fixed point
+    step: u64,
+}
+
+impl Sampler {
+    pub const fn new() -> Self {
+        Self {
+            volume: 255,
+            samples: &[],
+            looping: false,
+            position: 0,
+            step: 0,
+        }
+    }
+
+    /// Plays samples recorded at `rate` samples per second, from the start
+    pub fn play(&mut self, samples: &'static [i8], rate: u32) {
+        self.samples = samples;
+        self.looping = false;
+        self.position = 0;
+        self.step = ((rate as u64) << 32) / SAMPLE_RATE as u64;
+    }
+
+    /// Like [`Sampler::play`], but starts over at the end until stopped
+    pub fn play_looped(&mut self, samples: &'static [i8], rate: u32) {
+        self.play(samples, rate);
+        self.looping = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.samples = &[];
+    }
+
+    /// Whether the channel is making any sound
+    pub fn is_playing(&self) -> bool {
+        !self.samples.is_empty()
+    }
+
+    pub(crate) fn next(&mut self) -> i32 {
+        let mut index = (self.position >> 32) as usize;
+        if index >= self.samples.len() {
+            if !self.looping || self.samples.is_empty() {
+                self.samples = &[];
+                return 0;
+            }
+            self.position %= (self.samples.len() as u64) << 32;
+            index = (self.position >> 32) as usize;
+        }
+
+        self.position += self.step;
+        self.samples[index] as i32 * AMPLITUDE / 128 * self.volume as i32 / 255
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,129 @@
+use crate::SAMPLE_RATE;
+
+/// Envelope level at full volume
+pub(crate) const MAX_LEVEL: u32 = 1 << 16;
+
+/// How a note's volume changes over time
+///
+/// When a note starts, the volume rises to full over `attack` milliseconds,
+/// then falls to `sustain` over `decay` milliseconds, and stays there until
+/// the note is released. It then falls to zero over `release` milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Adsr {
+    pub attack: u16,
+    pub decay: u16,
+    /// Volume while the note is held, from 0 to 255
+    pub sustain: u8,
+    pub release: u16,
+}
+
+impl Adsr {
+    /// Full volume for as long as the note is held
+    pub const HOLD: Adsr = Adsr::new(0, 0, 255, 0);
+
+    /// A short blip that fades out by itself
+    pub const PLUCK: Adsr = Adsr::new(2, 150, 0, 0);
+
+    pub const fn new(attack: u16, decay: u16, sustain: u8, release: u16) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain,
+            release,
+        }
+    }
+
+    const fn sustain_level(&self) -> u32 {
+        self.sustain as u32 * MAX_LEVEL / 255
+    }
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self::HOLD
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// The current volume of a note, following an [`Adsr`]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Envelope {
+    stage: Stage,
+    level: u32,
+    /// Change in level per sample in the current stage
+    step: u32,
+}
+
+impl Envelope {
+    pub const fn new() -> Self {
+        Self {
+            stage: Stage::Idle,
+            level: 0,
+            step: 0,
+        }
+    }
+
+    pub fn start(&mut self, adsr: &Adsr) {
+        self.stage = Stage::Attack;
+        self.step = step(adsr.attack, MAX_LEVEL - self.level);
+    }
+
+    pub fn release(&mut self, adsr: &Adsr) {
+        if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+            self.step = step(adsr.release, self.level);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    /// Advances the envelope by a sample, and returns its level
+    pub fn next(&mut self, adsr: &Adsr) -> u32 {
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Attack => {
+                self.level = (self.level + self.step).min(MAX_LEVEL);
+                if self.level == MAX_LEVEL {
+                    self.stage = Stage::Decay;
+                    self.step = step(adsr.decay, MAX_LEVEL - adsr.sustain_level());
+                }
+            }
+            Stage::Decay => {
+                let sustain = adsr.sustain_level();
+                self.level = self.level.saturating_sub(self.step).max(sustain);
+                if self.level == sustain {
+                    // nothing left to hear if it sustains at zero
+                    self.stage = if sustain == 0 {
+                        Stage::Idle
+                    } else {
+                        Stage::Sustain
+                    };
+                }
+            }
+            Stage::Sustain => self.level = adsr.sustain_level(),
+            Stage::Release => {
+                self.level = self.level.saturating_sub(self.step);
+                if self.level == 0 {
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        self.level
+    }
+}
+
+/// Level change per sample to cover `range` in `milliseconds`
+fn step(milliseconds: u16, range: u32) -> u32 {
+    let samples = milliseconds as u32 * SAMPLE_RATE / 1000;
+    range.div_ceil(samples.max(1)).max(1)
+}
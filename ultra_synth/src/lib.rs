@@ -0,0 +1,258 @@
+//! A tiny chiptune synthesizer for roms
+//!
+//! Has the channels of a classic sound chip: two pulse waves, a triangle wave,
+//! noise and playback of 8-bit samples. It doesn't allocate and doesn't need
+//! `std`, so it adds little to a rom's size.
+//!
+//! Start and stop notes as things happen in the game, and render a frame of
+//! samples at the end of every update:
+//!
+//! ```
+//! use ultra_crustaceous::AudioBuffer;
+//! use ultra_synth::{note, Adsr, Duty, Synth};
+//!
+//! let mut synth = Synth::new();
+//! let mut audio = AudioBuffer::default();
+//!
+//! // when the player picks up a coin
+//! synth.pulse1.duty = Duty::Quarter;
+//! synth.pulse1.envelope = Adsr::PLUCK;
+//! synth.pulse1.note_on(note(76));
+//!
+//! // at the end of update
+//! synth.render(audio.frame_mut());
+//! ```
+
+#![no_std]
+
+mod channel;
+mod envelope;
+
+pub use channel::{Duty, Noise, Pulse, Sampler, Triangle};
+pub use envelope::Adsr;
+
+/// Samples per second of the rendered audio
+pub const SAMPLE_RATE: u32 = ultra_crustaceous::AudioBuffer::SAMPLE_RATE as u32;
+
+/// All channels, mixed together
+#[derive(Clone, Copy, Debug)]
+pub struct Synth {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub sampler: Sampler,
+    /// Volume of the mix, from 0 to 255
+    pub volume: u8,
+}
+
+impl Synth {
+    pub const fn new() -> Self {
+        Self {
+            pulse1: Pulse::new(),
+            pulse2: Pulse::new(),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            sampler: Sampler::new(),
+            volume: 255,
+        }
+    }
+
+    /// Fills `out` with the next samples of all channels mixed together
+    ///
+    /// Pass [`AudioBuffer::frame_mut`](ultra_crustaceous::AudioBuffer::frame_mut)
+    /// to render a frame's worth.
+    pub fn render(&mut self, out: &mut [i16]) {
+        for sample in out {
+            let mix = self.pulse1.next()
+                + self.pulse2.next()
+                + self.triangle.next()
+                + self.noise.next()
+                + self.sampler.next();
+            let mix = mix * self.volume as i32 / 255;
+            *sample = mix.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+    }
+
+    /// Whether any channel is making sound
+    pub fn is_playing(&self) -> bool {
+        self.pulse1.is_playing()
+            || self.pulse2.is_playing()
+            || self.triangle.is_playing()
+            || self.noise.is_playing()
+            || self.sampler.is_playing()
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Frequency in Hz of a MIDI note number, 69 is the A at 440 Hz and 60 is
+/// middle C
+pub fn note(number: u8) -> f32 {
+    // the octave from middle C
+    const OCTAVE: [f32; 12] = [
+        261.63, 277.18, 293.66, 311.13, 329.63, 349.23, 369.99, 392.00, 415.30, 440.00, 466.16,
+        493.88,
+    ];
+
+    let mut frequency = OCTAVE[number as usize % 12];
+    let octave = number as i32 / 12 - 5;
+    for _ in 0..octave {
+        frequency *= 2.0;
+    }
+    for _ in octave..0 {
+        frequency /= 2.0;
+    }
+    frequency
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::{vec, vec::Vec};
+    use ultra_crustaceous::AudioBuffer;
+
+    const FRAME: usize = AudioBuffer::SAMPLES_PER_FRAME;
+
+    fn render(synth: &mut Synth, frames: usize) -> Vec<i16> {
+        let mut samples = vec![0; frames * FRAME];
+        for frame in samples.chunks_mut(FRAME) {
+            synth.render(frame);
+        }
+        samples
+    }
+
+    #[test]
+    fn notes() {
+        assert_eq!(note(69), 440.0);
+        assert_eq!(note(81), 880.0);
+        assert_eq!(note(57), 220.0);
+        assert!((note(60) - 261.63).abs() < 0.01);
+    }
+
+    #[test]
+    fn silent_by_default() {
+        let mut synth = Synth::new();
+        assert!(render(&mut synth, 2).iter().all(|sample| *sample == 0));
+        assert!(!synth.is_playing());
+    }
+
+    #[test]
+    fn pulse_duty() {
+        let mut synth = Synth::new();
+        synth.pulse1.duty = Duty::Quarter;
+        synth.pulse1.note_on(441.0);
+
+        let samples = render(&mut synth, 60);
+        let high = samples.iter().filter(|sample| **sample > 0).count();
+        assert_eq!(high * 100 / samples.len(), 25);
+        assert_eq!(samples.iter().max(), Some(&6000));
+    }
+
+    #[test]
+    fn triangle_range() {
+        let mut synth = Synth::new();
+        synth.triangle.note_on(note(45));
+
+        let samples = render(&mut synth, 10);
+        let (min, max) = (samples.iter().min().unwrap(), samples.iter().max().unwrap());
+        assert!(*min <= -5900 && *max >= 5900);
+        // no jumps, it's a triangle
+        assert!(samples.windows(2).all(|w| (w[0] - w[1]).abs() < 100));
+    }
+
+    #[test]
+    fn envelope() {
+        let mut synth = Synth::new();
+        synth.pulse1.envelope = Adsr::new(10, 10, 128, 20);
+        synth.pulse1.note_on(note(69));
+
+        let peak = |samples: &[i16]| samples.iter().map(|s| s.abs()).max().unwrap();
+
+        let samples = render(&mut synth, 2);
+        // a quarter of the way through the attack
+        assert!((peak(&samples[..110]) - 1500).abs() < 50);
+        assert_eq!(peak(&samples), 6000);
+        // sustaining at half volume
+        assert!((peak(&samples[1000..]) - 3000).abs() < 50);
+
+        synth.pulse1.note_off();
+        let samples = render(&mut synth, 2);
+        assert_eq!(peak(&samples[900..]), 0);
+        assert!(!synth.is_playing());
+    }
+
+    #[test]
+    fn pluck_stops_by_itself() {
+        let mut synth = Synth::new();
+        synth.noise.envelope = Adsr::PLUCK;
+        synth.noise.note_on(8000.0);
+
+        assert!(render(&mut synth, 10).iter().any(|sample| *sample != 0));
+        assert!(!synth.is_playing());
+        assert!(render(&mut synth, 1).iter().all(|sample| *sample == 0));
+    }
+
+    #[test]
+    fn sampler() {
+        static SAMPLES: [i8; 4] = [127, -128, 64, 0];
+
+        let mut synth = Synth::new();
+        synth.sampler.play(&SAMPLES, SAMPLE_RATE / 2);
+
+        let samples = render(&mut synth, 1);
+        assert_eq!(
+            samples[..10],
+            [5953, 5953, -6000, -6000, 3000, 3000, 0, 0, 0, 0]
+        );
+        assert!(!synth.is_playing());
+
+        synth.sampler.play_looped(&SAMPLES, SAMPLE_RATE);
+        let samples = render(&mut synth, 1);
+        assert_eq!(samples[4..6], [5953, -6000]);
+        assert!(synth.is_playing());
+    }
+
+    /// Plays a short jingle with every channel
+    #[test]
+    fn jingle() {
+        let mut synth = Synth::new();
+        synth.pulse1.envelope = Adsr::new(5, 60, 100, 80);
+        synth.pulse2.duty = Duty::Eighth;
+        synth.pulse2.envelope = Adsr::PLUCK;
+        synth.noise.envelope = Adsr::new(0, 40, 0, 0);
+
+        let peak = |samples: &[i16]| samples.iter().map(|s| s.abs()).max().unwrap();
+
+        for (beat, melody) in [72, 76, 79, 84].into_iter().enumerate() {
+            synth.pulse1.note_on(note(melody));
+            synth.pulse2.note_on(note(melody + 12));
+            synth.triangle.note_on(note(48 + beat as u8 % 2 * 7));
+            synth
+                .noise
+                .note_on(if beat % 2 == 0 { 2000.0 } else { 10000.0 });
+            // louder than any one channel
+            assert!(peak(&render(&mut synth, 10)) > 6000, "beat {beat}");
+
+            synth.pulse1.note_off();
+            render(&mut synth, 5);
+            // the release and the plucks are shorter than the rest between
+            // beats, only the bass goes on
+            assert!(!synth.pulse1.is_playing(), "beat {beat}");
+            assert!(!synth.pulse2.is_playing(), "beat {beat}");
+            assert!(!synth.noise.is_playing(), "beat {beat}");
+            assert!(synth.triangle.is_playing(), "beat {beat}");
+        }
+
+        synth.triangle.note_off();
+        render(&mut synth, 1);
+        assert!(!synth.is_playing());
+        assert_eq!(peak(&render(&mut synth, 1)), 0);
+    }
+}
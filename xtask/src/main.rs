@@ -1,14 +1,20 @@
 // TODO: move all of this into easy-to-use crate
 
 use lazy_static::lazy_static;
-use log::info;
+use log::{error, info, warn};
 use std::{
-    fs::{create_dir_all, rename, File},
+    fs::{copy, create_dir_all, remove_dir_all, remove_file, File},
     io::{Read, Seek, Write},
     path::Path,
 };
+use ultra_meta::Metadata;
+use ultra_movie::Movie;
+use ultra_package::{Manifest, Package, MANIFEST_NAME};
 use walkdir::WalkDir;
-use xtask_wasm::{anyhow::Result, clap};
+use xtask_wasm::{
+    anyhow::{bail, Result},
+    clap,
+};
 use zip::{result::ZipError, write::FileOptions};
 
 #[derive(clap::Parser)]
@@ -20,8 +26,11 @@ struct Opt {
 }
 
 #[derive(clap::Parser)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     Dist(Build),
+    /// Checks that an already built rom follows the spec
+    Check(Check),
 }
 
 #[derive(clap::Parser)]
@@ -33,6 +42,13 @@ struct Build {
     package: Option<String>,
 }
 
+#[derive(clap::Parser)]
+struct Check {
+    /// The package to check
+    #[clap(index = 1)]
+    package: Option<String>,
+}
+
 fn main() -> Result<()> {
     let opt: Opt = clap::Parser::parse();
 
@@ -42,17 +58,8 @@ fn main() -> Result<()> {
 
     match opt.cmd {
         Command::Dist(mut arg) => {
-            let package_name = arg.package.as_ref().unwrap_or_else(|| {
-                &cargo_data()
-                    .root_package()
-                    .expect(
-                        // todo: maybe we should just build all ultra packages then?
-                        "No root crate, please provide rom crate name or run from rom subdirectory",
-                    )
-                    .name
-            });
-            let workspace_root = &cargo_data().workspace_root;
-            let dist_root = format!("{workspace_root}/dist");
+            let package_name = package_name(&arg.package);
+            let dist_root = dist_root();
 
             info!("Generating package: {package_name}...");
 
@@ -66,23 +73,229 @@ fn main() -> Result<()> {
                 .shrink(3)
                 .optimize(&dist_result.wasm)?;
 
-            let size = std::fs::metadata(&dist_result.wasm)?.len();
-            info!("File size: {}", bytesize::ByteSize(size));
+            // the last build is about to be removed, keep its size to compare
+            let previous_size = std::fs::metadata(format!("{dist_dir}/main.wasm"))
+                .ok()
+                .map(|metadata| metadata.len());
 
             info!("Creating dist dir");
+            if Path::new(&dist_dir).is_dir() {
+                remove_dir_all(&dist_dir)?;
+            }
             create_dir_all(&dist_dir)?;
 
-            rename(&dist_result.wasm, format!("{dist_dir}/main.wasm"))?;
+            // after wasm-opt, which would strip the section
+            let metadata = package_metadata(package_name)?;
+            let wasm = metadata.embed(&std::fs::read(&dist_result.wasm)?)?;
+            std::fs::write(format!("{dist_dir}/main.wasm"), &wasm)?;
+            remove_file(&dist_result.wasm)?;
+
+            let size = wasm.len() as u64;
+            match previous_size {
+                Some(previous) if previous != size => info!(
+                    "File size: {} ({} {} than the last build)",
+                    bytesize::ByteSize(size),
+                    bytesize::ByteSize(previous.abs_diff(size)),
+                    if size < previous { "smaller" } else { "larger" },
+                ),
+                _ => info!("File size: {}", bytesize::ByteSize(size)),
+            }
+
+            check_rom(&format!("{dist_dir}/main.wasm"))?;
 
-            let file = File::create(format!("{dist_root}/{package_name}.ultra.zip"))?;
+            let manifest = package_manifest(package_name, metadata, &wasm, &dist_dir)?;
+            std::fs::write(format!("{dist_dir}/{MANIFEST_NAME}"), manifest.to_toml())?;
+
+            let zip_path = format!("{dist_root}/{package_name}.ultra.zip");
+            let file = File::create(&zip_path)?;
 
             zip_dir(&dist_dir, &dist_dir, file, zip::CompressionMethod::Stored)?;
+
+            Package::open(&zip_path)?;
+            info!("Package: {zip_path}");
+        }
+        Command::Check(arg) => {
+            let package_name = package_name(&arg.package);
+            let wasm_path = format!("{}/{package_name}/main.wasm", dist_root());
+
+            if !Path::new(&wasm_path).is_file() {
+                bail!("{wasm_path} not found, run `cargo xtask dist {package_name}` first");
+            }
+
+            check_rom(&wasm_path)?;
         }
     }
 
     Ok(())
 }
 
+/// Logs a report of the rom at the given path, fails if it breaks the spec
+fn check_rom(wasm_path: &str) -> Result<()> {
+    info!("Checking {wasm_path}");
+
+    let report = ultra_host::check::check(&std::fs::read(wasm_path)?)?;
+
+    match (report.initial_pages, report.maximum_pages) {
+        (Some(initial), Some(maximum)) => info!("Memory: {initial} pages, max {maximum}"),
+        (Some(initial), None) => info!("Memory: {initial} pages, no max"),
+        _ => {}
+    }
+
+    match Metadata::read(&std::fs::read(wasm_path)?)? {
+        Some(metadata) => {
+            info!(
+                "Title: {} {} by {}, {} player(s)",
+                metadata.title, metadata.version, metadata.author, metadata.players
+            );
+            for (input, description) in metadata.controls.iter() {
+                info!("Control: {input:?} - {description}");
+            }
+        }
+        None => warn!("Metadata: none, hosts will only know the file name"),
+    }
+
+    if report.has_palette {
+        info!("Palette: exported");
+    } else {
+        warn!("Palette: not exported, hosts will use the default palette");
+    }
+
+    if report.has_audio {
+        info!("Audio: exported");
+    } else {
+        info!("Audio: not exported, the rom is silent");
+    }
+
+    if report.has_save_data {
+        info!("Save data: exported");
+    } else {
+        info!("Save data: not exported, the rom forgets everything between sessions");
+    }
+
+    if report.has_init {
+        info!("Init: exported");
+    } else {
+        info!("Init: not exported, the rom always uses the default seed");
+    }
+
+    for violation in &report.violations {
+        error!("{violation}");
+    }
+
+    if !report.is_ok() {
+        bail!("rom breaks the spec in {} ways", report.violations.len());
+    }
+
+    info!("Rom follows the spec");
+
+    Ok(())
+}
+
+/// `[package.metadata.ultra]` of the package, with the package's own fields
+/// filling in the gaps
+fn package_metadata(package_name: &str) -> Result<Metadata> {
+    let Some(package) = cargo_data()
+        .packages
+        .iter()
+        .find(|package| package.name == package_name)
+    else {
+        bail!("no package named {package_name}");
+    };
+
+    let mut metadata = match &package.metadata["ultra"] {
+        ultra if ultra.is_null() => Metadata::default(),
+        ultra => Metadata::from_json(ultra.to_string().as_bytes())?,
+    };
+
+    if metadata.title.is_empty() {
+        metadata.title = package.name.clone();
+    }
+    if metadata.version.is_empty() {
+        metadata.version = package.version.to_string();
+    }
+    if metadata.description.is_empty() {
+        metadata.description = package.description.clone().unwrap_or_default();
+    }
+    if metadata.author.is_empty() {
+        metadata.author = package.authors.join(", ");
+    }
+
+    Ok(metadata)
+}
+
+/// Manifest for the package's zip, copying the cover, replays and assets
+/// listed in `[package.metadata.ultra]` to the dist dir
+fn package_manifest(
+    package_name: &str,
+    metadata: Metadata,
+    wasm: &[u8],
+    dist_dir: &str,
+) -> Result<Manifest> {
+    let package = cargo_data()
+        .packages
+        .iter()
+        .find(|package| package.name == package_name)
+        .expect("package was just built");
+    let package_dir = package
+        .manifest_path
+        .parent()
+        .expect("manifest is in a dir");
+    let ultra = &package.metadata["ultra"];
+
+    let mut manifest = Manifest::new(metadata);
+
+    manifest.cover = ultra["cover"].as_str().map(String::from);
+
+    let rom_hash = ultra_host::hash_rom(wasm);
+    for replay in ultra["replays"].as_array().into_iter().flatten() {
+        let Some(replay) = replay.as_str() else {
+            bail!("replays must be paths");
+        };
+        let movie = Movie::read(File::open(package_dir.join(replay))?)?;
+        if movie.rom_hash != rom_hash {
+            // every change to the rom invalidates its movies
+            warn!("Skipping replay {replay}, it was recorded with a different build");
+            continue;
+        }
+        manifest.replays.push(replay.into());
+    }
+
+    for asset in ultra["assets"].as_array().into_iter().flatten() {
+        let Some(asset) = asset.as_str() else {
+            bail!("assets must be paths");
+        };
+        manifest.assets.push(asset.into());
+    }
+
+    // checks the paths before copying anything
+    let manifest = Manifest::from_toml(&manifest.to_toml())?;
+
+    for path in manifest.files().filter(|path| *path != manifest.entry) {
+        let destination = Path::new(dist_dir).join(path);
+        create_dir_all(destination.parent().expect("joined to dist dir"))?;
+        copy(package_dir.join(path), destination)?;
+    }
+
+    Ok(manifest)
+}
+
+fn package_name(package: &Option<String>) -> &str {
+    package.as_ref().unwrap_or_else(|| {
+        &cargo_data()
+            .root_package()
+            .expect(
+                // todo: maybe we should just build all ultra packages then?
+                "No root crate, please provide rom crate name or run from rom subdirectory",
+            )
+            .name
+    })
+}
+
+fn dist_root() -> String {
+    let workspace_root = &cargo_data().workspace_root;
+    format!("{workspace_root}/dist")
+}
+
 fn zip_dir<T>(
     src_dir: &str,
     prefix: &str,
@@ -119,7 +332,7 @@ where
             let mut f = File::open(path)?;
 
             f.read_to_end(&mut buffer)?;
-            zip.write_all(&*buffer)?;
+            zip.write_all(&buffer)?;
             buffer.clear();
         } else if !name.as_os_str().is_empty() {
             // Only if not root! Avoids path spec / warning